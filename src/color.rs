@@ -3,12 +3,14 @@ use std::fmt::{Debug, Display};
 use colored::*;
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Color {
     Yellow,
     Red,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Gameover {
     Win(crate::color::Color),
     Tie,
@@ -40,4 +42,20 @@ impl Color {
             Color::Red => Color::Yellow,
         }
     }
+
+    /// Letter used for this color's pieces in FEN strings
+    pub const fn fen_char(&self) -> char {
+        match self {
+            Color::Yellow => 'y',
+            Color::Red => 'r',
+        }
+    }
+
+    pub const fn from_fen_char(c: char) -> Option<Color> {
+        match c {
+            'y' => Some(Color::Yellow),
+            'r' => Some(Color::Red),
+            _ => None,
+        }
+    }
 }
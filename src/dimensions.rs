@@ -0,0 +1,168 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::ops::{
+    Add, BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not, Shl, Shr,
+};
+use tinyvec::ArrayVec;
+
+/// Widest board supported by any backend, a 16x7 board fills a u128 exactly
+pub const MAX_WIDTH: usize = 16;
+
+/// Integer type used to store one bit per cell of the board.
+pub trait Bitboard:
+    Copy
+    + Eq
+    + Hash
+    + Debug
+    + Send
+    + Sync
+    + 'static
+    + Add<Output = Self>
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitXor<Output = Self>
+    + Not<Output = Self>
+    + Shl<u32, Output = Self>
+    + Shr<u32, Output = Self>
+    + BitAndAssign
+    + BitOrAssign
+    + BitXorAssign
+{
+    const ZERO: Self;
+    const ONE: Self;
+    const BITS: u32;
+
+    fn count_ones(self) -> u32;
+    fn trailing_zeros(self) -> u32;
+    fn trailing_ones(self) -> u32;
+    fn leading_zeros(self) -> u32;
+    /// Convert a mask computed at compile time, dropping any bits that don't fit
+    fn from_u128(bits: u128) -> Self;
+    fn to_u128(self) -> u128;
+}
+
+macro_rules! impl_bitboard {
+    ($int:ty) => {
+        impl Bitboard for $int {
+            const ZERO: Self = 0;
+            const ONE: Self = 1;
+            const BITS: u32 = <$int>::BITS;
+
+            #[inline(always)]
+            fn count_ones(self) -> u32 {
+                self.count_ones()
+            }
+
+            #[inline(always)]
+            fn trailing_zeros(self) -> u32 {
+                self.trailing_zeros()
+            }
+
+            #[inline(always)]
+            fn trailing_ones(self) -> u32 {
+                self.trailing_ones()
+            }
+
+            #[inline(always)]
+            fn leading_zeros(self) -> u32 {
+                self.leading_zeros()
+            }
+
+            #[inline(always)]
+            fn from_u128(bits: u128) -> Self {
+                bits as $int
+            }
+
+            #[inline(always)]
+            fn to_u128(self) -> u128 {
+                self as u128
+            }
+        }
+    };
+}
+
+impl_bitboard!(u64);
+impl_bitboard!(u128);
+
+/// Size of the board, and the bitboard type that is big enough to hold it.
+///
+/// Every column takes `HEIGHT + 1` bits, the extra bit being a sentinel row that stops
+/// lines from wrapping from the top of one column to the bottom of the next.
+pub trait Dimensions: Copy + Eq + Hash + Debug + Default + Send + Sync + 'static {
+    /// Number of columns
+    const WIDTH: u8;
+    /// Number of rows
+    const HEIGHT: u8;
+    type Bits: Bitboard;
+
+    /// Distance in bits between the bottom cells of neighbouring columns
+    const STRIDE: u8 = Self::HEIGHT + 1;
+    /// Number of playable cells
+    const CELLS: u32 = Self::WIDTH as u32 * Self::HEIGHT as u32;
+    /// The playable cells of a single column, starting at bit 0
+    const COLUMN_MASK: u128 = (1 << Self::HEIGHT) - 1;
+    /// Every playable cell, leaving out the sentinel rows
+    const FULL_BOARD_MASK: u128 = full_board_mask(Self::WIDTH, Self::STRIDE, Self::COLUMN_MASK);
+    /// The top cell of every column
+    const TOP_ROW_MASK: u128 = Self::FULL_BOARD_MASK & !(Self::FULL_BOARD_MASK >> 1);
+
+    /// Columns ordered from the center outwards, preferring the left column on ties
+    fn column_order() -> ArrayVec<[u8; MAX_WIDTH]> {
+        let mut columns: ArrayVec<[u8; MAX_WIDTH]> = (0..Self::WIDTH).collect();
+        columns.sort_by_key(|&column| (2 * column as i32 - (Self::WIDTH as i32 - 1)).abs());
+        columns
+    }
+}
+
+const fn full_board_mask(width: u8, stride: u8, column_mask: u128) -> u128 {
+    let mut mask = 0;
+    let mut column = 0;
+    while column < width {
+        mask |= column_mask << (column as u32 * stride as u32);
+        column += 1;
+    }
+    mask
+}
+
+/// A `W` x `H` board stored in a u64, which fits boards with up to 64 bits including the sentinel rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Dims<const W: u8, const H: u8>;
+
+impl<const W: u8, const H: u8> Dimensions for Dims<W, H> {
+    const WIDTH: u8 = {
+        assert!(W >= 1 && W as usize <= MAX_WIDTH, "Unsupported board width");
+        assert!(
+            W as u32 * (H as u32 + 1) <= u64::BITS,
+            "Board does not fit in a u64, use WideDims instead"
+        );
+        W
+    };
+    const HEIGHT: u8 = {
+        assert!(H >= 1, "Unsupported board height");
+        H
+    };
+    type Bits = u64;
+}
+
+/// A `W` x `H` board stored in a u128, for boards too big for [`Dims`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct WideDims<const W: u8, const H: u8>;
+
+impl<const W: u8, const H: u8> Dimensions for WideDims<W, H> {
+    const WIDTH: u8 = {
+        assert!(W >= 1 && W as usize <= MAX_WIDTH, "Unsupported board width");
+        assert!(
+            W as u32 * (H as u32 + 1) <= u128::BITS,
+            "Board does not fit in a u128"
+        );
+        W
+    };
+    const HEIGHT: u8 = {
+        assert!(H >= 1, "Unsupported board height");
+        H
+    };
+    type Bits = u128;
+}
+
+/// The classic 7 columns by 6 rows
+pub type Standard = Dims<7, 6>;
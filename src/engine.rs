@@ -1,64 +1,205 @@
 use crate::color::Gameover;
-use crate::gamestate::GameState;
+use crate::dimensions::Dimensions;
+use crate::gamestate::{GameState, Move, MAX_MOVES};
+use crate::rules::Rules;
 use crate::transposition;
+use rand_mt::Mt64;
 use rayon::prelude::*;
+use std::fmt;
 use tinyvec::ArrayVec;
 
-// Prioritize columns near the center
-const COLUMN_ORDERING: [u8; 7] = [3, 2, 4, 1, 5, 0, 6];
-const WINNING_EVAL: i32 = 1000; // Value for a winning move
+pub(crate) const WINNING_EVAL: i32 = 1000; // Value for a winning move
 
-pub fn negamax_entrypoint(board: &GameState, table: &mut transposition::Table) -> (u8, i32) {
-    let mut results: Vec<(u8, i32)> = COLUMN_ORDERING
-        .into_par_iter()
-        // .into_iter()
-        .filter(|&column| board.get_height(column) < 6) // filter out full columns
-        .map(|column| {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+    Perfect,
+}
+
+/// Settings that control how strongly the engine plays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EngineConfig {
+    /// Maximum number of plies to search below the root move, or `None` to search to the end of the game.
+    /// Threats further away than this are invisible to the engine.
+    pub max_depth: Option<u16>,
+    /// Chance (0.0 to 1.0) of deliberately playing a sub-optimal column.
+    /// Columns are picked with a weight that shrinks as their score falls further behind the best one.
+    pub blunder_chance: f64,
+    /// Pick at random between columns tied for the best eval, instead of always taking the most central one.
+    pub randomize_ties: bool,
+}
+
+impl EngineConfig {
+    pub const fn from_difficulty(difficulty: Difficulty) -> Self {
+        match difficulty {
+            Difficulty::Easy => EngineConfig {
+                max_depth: Some(2),
+                blunder_chance: 0.3,
+                randomize_ties: false,
+            },
+            Difficulty::Medium => EngineConfig {
+                max_depth: Some(6),
+                blunder_chance: 0.1,
+                randomize_ties: false,
+            },
+            Difficulty::Hard => EngineConfig {
+                max_depth: Some(12),
+                blunder_chance: 0.0,
+                randomize_ties: false,
+            },
+            Difficulty::Perfect => EngineConfig {
+                max_depth: None,
+                blunder_chance: 0.0,
+                randomize_ties: false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for EngineConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.max_depth {
+            Some(depth) => write!(f, "depth {}", depth)?,
+            None => write!(f, "full depth")?,
+        }
+        write!(f, ", blunder chance {}", self.blunder_chance)?;
+        if self.randomize_ties {
+            write!(f, ", random ties")?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self::from_difficulty(Difficulty::Perfect)
+    }
+}
+
+/// Evaluate every legal move and choose one the way `config` asks for.
+/// Returns the chosen move with its eval, from the point of view of the player to move.
+pub fn negamax_entrypoint<D: Dimensions>(
+    board: &GameState<D>,
+    table: &transposition::Table<D>,
+    config: &EngineConfig,
+    rng: &mut Mt64,
+) -> (Move, i32) {
+    choose_move(&evaluate_moves(&[], board, table, config), config, rng)
+}
+
+/// Eval of every legal move from the point of view of the player to move, best first.
+/// Moves with equal evals keep the order of [`GameState::legal_moves`], which puts central columns first.
+///
+/// `history` holds the positions the game went through before `board`, such as [`Game::history`](crate::game::Game::history),
+/// so that under PopOut rules the search counts repetitions the same way the game does.
+pub fn evaluate_moves<D: Dimensions>(
+    history: &[GameState<D>],
+    board: &GameState<D>,
+    table: &transposition::Table<D>,
+    config: &EngineConfig,
+) -> Vec<(Move, i32)> {
+    // A depth-limited search produces inexact evals, so it must not read from or write to the table
+    let table = if config.max_depth.is_none() {
+        Some(table)
+    } else {
+        None
+    };
+    // Prioritize columns near the center
+    let mut results: Vec<(Move, i32)> = board
+        .legal_moves()
+        .par_iter()
+        // .iter()
+        .map(|&mv| {
             // Evaluate each possible move
             let mut new_board = board.clone();
-            new_board.make_move(column);
+            new_board.play(mv);
             // We must check for terminal states because negamax does not check itself for termination.
             if let Some(eval) = evaluate_termination(&mut new_board, 0, table) {
-                let eval = -eval;
-                println!(
-                    "Column {} is a terminal move with eval {}",
-                    column + 1,
-                    eval
-                );
-                (column, eval)
+                (mv, -eval)
             } else {
-                let eval = -negamax(&mut new_board, -10_000, 10_000, 0, table);
-                (column, eval)
+                // Positions seen on the way to a node count as repetitions under PopOut rules
+                let mut path = history.to_vec();
+                path.push(board.clone());
+                let (eval, _) = negamax(
+                    &mut new_board,
+                    -10_000,
+                    10_000,
+                    0,
+                    config.max_depth,
+                    table,
+                    &mut path,
+                );
+                (mv, -eval)
             }
         })
         .collect();
 
     results.sort_by_key(|result| -result.1);
+    results
+}
 
-    println!();
-    for (col, eval) in results.iter() {
-        println!("Column {} evaluated to {}", col + 1, eval);
+/// Pick a move from `results`, as sorted by [`evaluate_moves`].
+/// Normally that is the best move, but `config` may ask for blunders or random choices between tied moves.
+pub fn choose_move(results: &[(Move, i32)], config: &EngineConfig, rng: &mut Mt64) -> (Move, i32) {
+    let mut best = *results.first().expect("Must have at least one valid move");
+    let tied = results
+        .iter()
+        .take_while(|result| result.1 == best.1)
+        .count();
+    // Moves tied with the best one are just as strong, so they don't count as blunders
+    if tied < results.len() && random_unit(rng) < config.blunder_chance {
+        return choose_blunder(&results[tied..], best.1, rng);
+    }
+    if config.randomize_ties {
+        best = results[(rng.next_u64() % tied as u64) as usize];
     }
+    best
+}
+
+/// A uniformly distributed random number in `[0.0, 1.0)`.
+fn random_unit(rng: &mut Mt64) -> f64 {
+    (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
 
-    *results.first().expect("Must have at least one valid move")
+/// Pick one of the sub-optimal `moves` at random.
+/// Each move is weighted by `1 / (1 + gap)`, where gap is how far its eval falls behind `best_eval`.
+fn choose_blunder(moves: &[(Move, i32)], best_eval: i32, rng: &mut Mt64) -> (Move, i32) {
+    let weights: Vec<f64> = moves
+        .iter()
+        .map(|&(_, eval)| 1.0 / (1.0 + (best_eval - eval) as f64))
+        .collect();
+    let mut target = random_unit(rng) * weights.iter().sum::<f64>();
+    for (&result, weight) in moves.iter().zip(weights) {
+        if target < weight {
+            return result;
+        }
+        target -= weight;
+    }
+    *moves
+        .last()
+        .expect("Must have at least one sub-optimal move")
 }
 
 /// Eagerly evaluate the board for a winning move.
 /// This function checks if the `board` is game over
 /// and returns the evaluation if it is, otherwise returns None.
 /// Additionally, it stores the evaluation in the transposition table if the `game_state` is terminal.
-fn evaluate_termination(
-    board: &mut GameState,
+fn evaluate_termination<D: Dimensions>(
+    board: &mut GameState<D>,
     ply: u16,
-    table: &transposition::Table,
+    table: Option<&transposition::Table<D>>,
 ) -> Option<i32> {
     let eval = match board.gameover_state() {
-        Gameover::Win(color) => {
-            // If the game has ended, then the next person to play has lost
-            debug_assert_ne!(
-                color, board.current_player,
-                "Gameover state should not be Win for current player"
-            );
+        Gameover::Win(color) if color == board.current_player => {
+            // Only possible under PopOut rules, where popping can complete the opponent's line,
+            // or under misère rules, where the previous player completed their own line
+            // We subtract the ply to encourage winning quickly
+            Some(WINNING_EVAL - ply as i32)
+        }
+        Gameover::Win(_) => {
+            // If the game has ended, then usually the next person to play has lost
             // The current player has lost, so we return a negative eval
             // We add on the ply to encourage dragging out losing games
             Some(ply as i32 - WINNING_EVAL)
@@ -66,20 +207,39 @@ fn evaluate_termination(
         Gameover::Tie => Some(0),
         Gameover::None => None,
     }?;
-    transposition::store_entry(table, board, eval);
+    if let Some(table) = table {
+        transposition::store_entry(table, board, eval);
+    }
     Some(eval)
 }
 
-fn negamax(
-    board: &mut GameState,
+/// Search the `board` down to the end of the game, or until `ply` reaches `max_depth`.
+/// Positions cut off by `max_depth` are scored as 0, since their outcome is unknown.
+///
+/// Under PopOut rules, `path` holds the positions leading up to `board`.
+/// A position that comes up for the [`Rules::REPETITIONS_FOR_DRAW`]th time is scored as a draw, as in the game loop.
+///
+/// Also returns whether the eval depends on `path` through such a draw.
+/// Those evals are not stored in the table, since the same position reached another way may not be a draw.
+fn negamax<D: Dimensions>(
+    board: &mut GameState<D>,
     mut alpha: i32,
     beta: i32,
     ply: u16,
-    table: &transposition::Table,
-) -> i32 {
+    max_depth: Option<u16>,
+    table: Option<&transposition::Table<D>>,
+    path: &mut Vec<GameState<D>>,
+) -> (i32, bool) {
+    if board.rules.popout {
+        let repetitions = 1 + path.iter().filter(|&seen| seen == board).count();
+        if repetitions >= Rules::REPETITIONS_FOR_DRAW {
+            return (0, true);
+        }
+    }
+
     // Probe the transposition table to see if we have encountered this game state before
-    if let Some(eval) = transposition::probe_eval(table, board) {
-        return eval;
+    if let Some(eval) = table.and_then(|table| transposition::probe_eval(table, board)) {
+        return (eval, false);
     }
 
     // We don't need to check for a game over state here, because we already did from the parent node.
@@ -88,37 +248,56 @@ fn negamax(
     //     return eval;
     // }
 
-    // Generate all legal moves
-    let legal_moves: ArrayVec<[u8; 7]> = COLUMN_ORDERING
-        .iter()
-        .filter(|&&column| board.get_height(column) < 6)
-        .copied()
-        .collect();
+    // Generate all legal moves, prioritizing columns near the center
+    let legal_moves = board.legal_moves();
 
     // First, eagerly evaluate the board for a winning move
     // This is basically just calling the first half of `negamax` on each legal move
     // If we find a winning move, we can return immediately
-    for &column in legal_moves.iter() {
-        board.make_move(column);
+    let mut terminal_evals: ArrayVec<[Option<i32>; MAX_MOVES]> = ArrayVec::new();
+    for &mv in legal_moves.iter() {
+        board.play(mv);
         let eval = evaluate_termination(board, ply + 1, table);
-        board.undo_move(column);
+        board.unplay(mv);
         if let Some(eval) = eval {
-            // We found a winning move, so we can return it immediately.
             // We must negative the eval because the evaluate_termination() function works from the perspective of the current player,
             // but we are checking down one level, so we need to negate the eval to get back to the correct perspective.
-            // This could also be a tie, in which case we still return it.
+            // Normally, a terminal move is always a win or a tie, and we can return it immediately.
             // This is because when a move causes a tie, it's because it's the last move (and doesn't cause a win)
-            // A tie move is also necessarily the only possible move, so we can return it immediately.
-            return -eval;
+            // A tie move is also necessarily the only possible move.
+            // Under PopOut rules a pop can also lose or tie, and under misère rules completing a line loses,
+            // so only wins are returned immediately.
+            if eval < 0 || !(board.rules.popout || board.rules.misere) {
+                return (-eval, false);
+            }
         }
+        terminal_evals.push(eval);
+    }
+
+    // We have seen every immediate win, but the engine cannot look any further ahead
+    if max_depth.is_some_and(|max_depth| ply >= max_depth) {
+        return (0, false);
     }
 
     // Continue down the negamax tree, evaluating each move recursively
+    let original_alpha = alpha;
     let mut max_eval = -20_000;
-    for column in legal_moves {
-        board.make_move(column);
-        let eval = -negamax(board, -beta, -alpha, ply + 1, table);
-        board.undo_move(column);
+    let mut path_dependent = false;
+    if board.rules.popout {
+        path.push(board.clone());
+    }
+    for (mv, terminal_eval) in legal_moves.into_iter().zip(terminal_evals) {
+        let eval = match terminal_eval {
+            Some(eval) => -eval,
+            None => {
+                board.play(mv);
+                let (eval, repeated) =
+                    negamax(board, -beta, -alpha, ply + 1, max_depth, table, path);
+                board.unplay(mv);
+                path_dependent |= repeated;
+                -eval
+            }
+        };
         max_eval = max_eval.max(eval);
 
         alpha = alpha.max(max_eval);
@@ -127,7 +306,264 @@ fn negamax(
         }
     }
 
-    transposition::store_entry(table, board, max_eval);
+    if board.rules.popout {
+        path.pop();
+    }
+
+    // An eval outside the window is only a bound, since the search stopped early or every move was cut off below it
+    let exact = original_alpha < max_eval && max_eval < beta;
+    if let Some(table) = table.filter(|_| exact && !path_dependent) {
+        transposition::store_entry(table, board, max_eval);
+    }
+
+    (max_eval, path_dependent)
+}
 
-    max_eval
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table that grows as needed, rather than reserving room for a full solve like [`transposition::new_table`]
+    fn small_table<D: Dimensions>() -> transposition::Table<D> {
+        transposition::Table::default()
+    }
+
+    /// Plays the best move found within `depth` plies, without blunders or random tie breaks
+    fn test_config(depth: u16) -> EngineConfig {
+        EngineConfig {
+            max_depth: Some(depth),
+            blunder_chance: 0.0,
+            randomize_ties: false,
+        }
+    }
+
+    #[test]
+    fn test_depth_limited_engine_takes_immediate_win() {
+        // Yellow has three in a row on the bottom row and can win in column 4
+        let board: GameState = GameState::from_fen(
+            "......./......./......./......./rrr..../yyy....",
+            Some(crate::color::Color::Yellow),
+        )
+        .unwrap();
+        let table = small_table();
+        let config = test_config(2);
+        let (mv, eval) = negamax_entrypoint(&board, &table, &config, &mut Mt64::new_unseeded());
+        assert_eq!(mv, Move::Drop(3));
+        assert!(eval > 0);
+    }
+
+    #[test]
+    fn test_random_tie_break_is_reproducible_from_seed() {
+        // With no lookahead every column on an empty board is tied at 0
+        let board: GameState = GameState::new();
+        let table = small_table();
+        let config = EngineConfig {
+            randomize_ties: true,
+            ..test_config(0)
+        };
+        let play = |seed: u64, table: &transposition::Table| -> Vec<Move> {
+            let mut rng = Mt64::new(seed);
+            (0..20)
+                .map(|_| negamax_entrypoint(&board, table, &config, &mut rng).0)
+                .collect()
+        };
+        let first = play(42, &table);
+        assert_eq!(first, play(42, &table));
+        assert!(first.iter().any(|&mv| mv != first[0]));
+    }
+
+    #[test]
+    fn test_blunders_are_worse_than_best_move() {
+        // Every column but column 4 is tied at 0, and column 4 wins
+        let board: GameState = GameState::from_fen(
+            "......./......./......./......./rrr..../yyy....",
+            Some(crate::color::Color::Yellow),
+        )
+        .unwrap();
+        let table = small_table();
+        let config = EngineConfig {
+            blunder_chance: 1.0,
+            ..test_config(0)
+        };
+        let mut rng = Mt64::new(3);
+        for _ in 0..20 {
+            let (mv, eval) = negamax_entrypoint(&board, &table, &config, &mut rng);
+            assert_ne!(mv, Move::Drop(3));
+            assert_eq!(eval, 0);
+        }
+
+        // With every move tied there is nothing worse to play, so the most central column is kept
+        let board: GameState = GameState::new();
+        let (mv, _) = negamax_entrypoint(&board, &table, &config, &mut rng);
+        assert_eq!(mv, Move::Drop(3));
+    }
+
+    #[test]
+    fn test_evaluate_moves_puts_best_first() {
+        let board: GameState = GameState::from_fen(
+            "......./......./......./......./rrr..../yyy....",
+            Some(crate::color::Color::Yellow),
+        )
+        .unwrap();
+        let table = small_table();
+        let config = test_config(0);
+        let evals = evaluate_moves(&[], &board, &table, &config);
+        assert_eq!(evals.len(), 7);
+        assert_eq!(evals[0].0, Move::Drop(3));
+        assert!(evals[0].1 > 0);
+        assert!(evals[1..].iter().all(|&(_, eval)| eval == 0));
+        assert_eq!(choose_move(&evals, &config, &mut Mt64::new(1)), evals[0]);
+    }
+
+    #[test]
+    fn test_engine_on_wide_board() {
+        use crate::dimensions::WideDims;
+
+        // Yellow can only win by playing in the rightmost column
+        let board = GameState::<WideDims<9, 7>>::from_fen(
+            "........./........./........./........./........y/........y/rrr.....y",
+            Some(crate::color::Color::Yellow),
+        )
+        .unwrap();
+        let table = small_table();
+        let config = EngineConfig {
+            blunder_chance: 0.0,
+            ..EngineConfig::from_difficulty(Difficulty::Easy)
+        };
+        let (mv, _) = negamax_entrypoint(&board, &table, &config, &mut Mt64::new_unseeded());
+        assert_eq!(mv, Move::Drop(8));
+    }
+
+    #[test]
+    fn test_engine_finds_winning_pop() {
+        // Popping the bottom of column 1 drops Yellow's piece into the middle row, completing four in a row
+        let mut board: GameState = GameState::from_fen(
+            "......./......./......./y....../ryyy..r/yrry.rr",
+            Some(crate::color::Color::Yellow),
+        )
+        .unwrap();
+        board.rules.popout = true;
+        let table = small_table();
+        let config = test_config(2);
+        let (mv, eval) = negamax_entrypoint(&board, &table, &config, &mut Mt64::new_unseeded());
+        assert_eq!(mv, Move::Pop(0));
+        assert!(eval > 0);
+    }
+
+    #[test]
+    fn test_repetition_draws_are_not_stored() {
+        let mut board: GameState = GameState::from_fen(
+            "......./......./......./......./......./y.....r",
+            Some(crate::color::Color::Yellow),
+        )
+        .unwrap();
+        board.rules.popout = true;
+        let table = small_table();
+
+        // The third time the position comes up is a draw, but only because of how it was reached
+        let mut path = vec![board.clone(); Rules::REPETITIONS_FOR_DRAW - 1];
+        let result = negamax(
+            &mut board,
+            -10_000,
+            10_000,
+            0,
+            None,
+            Some(&table),
+            &mut path,
+        );
+        assert_eq!(result, (0, true));
+        assert_eq!(transposition::probe_eval(&table, &board), None);
+
+        // One earlier visit isn't enough for a draw yet
+        let mut path = vec![board.clone(); Rules::REPETITIONS_FOR_DRAW - 2];
+        let (_, repeated) = negamax(&mut board, -10_000, 10_000, 0, Some(1), None, &mut path);
+        assert!(!repeated);
+    }
+
+    #[test]
+    fn test_engine_avoids_own_line_under_misere() {
+        // Column 4 would be a win in normal play, but completes Yellow's own line under misère rules
+        let mut board: GameState = GameState::from_fen(
+            "......./......./......./......./rrr..../yyy....",
+            Some(crate::color::Color::Yellow),
+        )
+        .unwrap();
+        board.rules.misere = true;
+        let table = small_table();
+        let config = test_config(2);
+        let (mv, _) = negamax_entrypoint(&board, &table, &config, &mut Mt64::new_unseeded());
+        assert_ne!(mv, Move::Drop(3));
+    }
+
+    #[test]
+    fn test_table_keeps_rules_apart() {
+        use crate::dimensions::Dims;
+
+        let normal = GameState::<Dims<4, 4>>::with_rules(Rules {
+            connect: 3,
+            ..Rules::default()
+        });
+        let misere = GameState::with_rules(Rules {
+            misere: true,
+            ..normal.rules
+        });
+        let config = EngineConfig::default();
+        let fresh = evaluate_moves(&[], &misere, &small_table(), &config);
+
+        // A table already filled by a search under other rules must not change the result
+        let shared = small_table();
+        evaluate_moves(&[], &normal, &shared, &config);
+        assert_eq!(evaluate_moves(&[], &misere, &shared, &config), fresh);
+    }
+
+    #[test]
+    fn test_search_counts_repetitions_from_game_history() {
+        use crate::game::Game;
+
+        let popout = Rules {
+            popout: true,
+            ..Rules::default()
+        };
+        let start: GameState = GameState::from_fen_with_rules(
+            "......./......./......./......./......./yyy.rrr",
+            Some(crate::color::Color::Red),
+            popout,
+        )
+        .unwrap();
+        let mut game = Game::new(start);
+        // Both players pop their outside piece and drop it back in, until Yellow could drop into column 1 to bring
+        // back the starting position for the third time
+        for mv in [
+            Move::Pop(4),
+            Move::Pop(0),
+            Move::Drop(4),
+            Move::Drop(0),
+            Move::Pop(4),
+            Move::Pop(0),
+            Move::Drop(4),
+        ] {
+            game.play(mv).unwrap();
+        }
+
+        let table = small_table();
+        let config = test_config(1);
+        let eval_of = |evals: Vec<(Move, i32)>| {
+            evals
+                .into_iter()
+                .find(|&(mv, _)| mv == Move::Drop(0))
+                .unwrap()
+                .1
+        };
+        // On its own the move lets Red win in column 4, but in this game it ends in a draw first
+        assert!(eval_of(evaluate_moves(&[], game.board(), &table, &config)) < 0);
+        assert_eq!(
+            eval_of(evaluate_moves(
+                &game.history(),
+                game.board(),
+                &table,
+                &config
+            )),
+            0
+        );
+    }
 }
@@ -0,0 +1,209 @@
+use crate::color::Gameover;
+use crate::dimensions::{Dimensions, Standard};
+use crate::gamestate::{Classification, GameState, Move};
+use crate::rules::Rules;
+use std::fmt;
+
+/// Why a move could not be played, undone or redone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryError {
+    /// No moves have been made since the game started
+    NothingToUndo,
+    /// No moves have been undone since the last move was made
+    NothingToRedo,
+    /// The move is not legal in the current position
+    IllegalMove(Move),
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryError::NothingToUndo => write!(f, "there are no moves to undo"),
+            HistoryError::NothingToRedo => write!(f, "there are no moves to redo"),
+            HistoryError::IllegalMove(mv) => write!(f, "{} is not a legal move", mv),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+/// A [`GameState`] together with the moves that led to it, so moves can be taken back and replayed.
+///
+/// The history is kept out of `GameState` itself, so that positions reached by different move orders
+/// still compare and hash equal in the search.
+#[derive(Debug, Clone)]
+pub struct Game<D: Dimensions = Standard> {
+    /// Position the game started from, which may have been loaded from FEN
+    start: GameState<D>,
+    board: GameState<D>,
+    moves: Vec<Move>,
+    /// Moves that were undone, the most recently undone last
+    undone: Vec<Move>,
+}
+
+impl<D: Dimensions> Default for Game<D> {
+    fn default() -> Self {
+        Self::new(GameState::new())
+    }
+}
+
+impl<D: Dimensions> Game<D> {
+    pub fn new(start: GameState<D>) -> Self {
+        Game {
+            board: start.clone(),
+            start,
+            moves: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+
+    /// Position the game started from
+    pub fn start(&self) -> &GameState<D> {
+        &self.start
+    }
+
+    #[inline(always)]
+    pub fn board(&self) -> &GameState<D> {
+        &self.board
+    }
+
+    /// Moves made since the start, in order
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    /// Number of moves made since the start
+    pub fn ply(&self) -> usize {
+        self.moves.len()
+    }
+
+    /// Play `mv`, which forgets any moves that could have been redone.
+    pub fn play(&mut self, mv: Move) -> Result<(), HistoryError> {
+        if !self.board.play(mv) {
+            return Err(HistoryError::IllegalMove(mv));
+        }
+        self.moves.push(mv);
+        self.undone.clear();
+        Ok(())
+    }
+
+    /// Take back the last move and return it.
+    pub fn undo(&mut self) -> Result<Move, HistoryError> {
+        let mv = self.moves.pop().ok_or(HistoryError::NothingToUndo)?;
+        self.board.unplay(mv);
+        self.undone.push(mv);
+        Ok(mv)
+    }
+
+    /// Play the last undone move again and return it.
+    pub fn redo(&mut self) -> Result<Move, HistoryError> {
+        let mv = self.undone.pop().ok_or(HistoryError::NothingToRedo)?;
+        if !self.board.play(mv) {
+            // Can't happen, since the board is back in the position the move was undone from
+            self.undone.push(mv);
+            return Err(HistoryError::IllegalMove(mv));
+        }
+        self.moves.push(mv);
+        Ok(mv)
+    }
+
+    /// Positions the game went through before the current one, starting with the start position
+    pub fn history(&self) -> Vec<GameState<D>> {
+        let mut board = self.start.clone();
+        let mut positions = Vec::with_capacity(self.moves.len());
+        for &mv in self.moves.iter() {
+            positions.push(board.clone());
+            board.play(mv);
+        }
+        positions
+    }
+
+    /// How many times the current position has come up since the start, including right now
+    pub fn repetitions(&self) -> usize {
+        1 + self
+            .history()
+            .iter()
+            .filter(|&board| *board == self.board)
+            .count()
+    }
+
+    /// The winner or a tie once the game is over, counting a tie by repetition under PopOut rules.
+    /// A position where both players have a line can't be decided, so it counts as unfinished.
+    pub fn result(&self) -> Gameover {
+        match self.board.classify() {
+            Classification::Win(color) => Gameover::Win(color),
+            Classification::Tie => Gameover::Tie,
+            Classification::Ongoing
+                if self.board.rules.popout && self.repetitions() >= Rules::REPETITIONS_FOR_DRAW =>
+            {
+                Gameover::Tie
+            }
+            Classification::Ongoing | Classification::BothConnected => Gameover::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_and_redo() {
+        let mut game: Game = Game::default();
+        for column in [3, 3, 4] {
+            game.play(Move::Drop(column)).unwrap();
+        }
+        assert_eq!(game.ply(), 3);
+        assert_eq!(game.undo(), Ok(Move::Drop(4)));
+        assert_eq!(game.undo(), Ok(Move::Drop(3)));
+        assert_eq!(game.moves(), &[Move::Drop(3)]);
+        assert_eq!(game.redo(), Ok(Move::Drop(3)));
+        assert_eq!(game.ply(), 2);
+
+        // A new move replaces the moves that were undone
+        game.play(Move::Drop(0)).unwrap();
+        assert_eq!(game.redo(), Err(HistoryError::NothingToRedo));
+        assert_eq!(
+            game.board().board_fen(),
+            "......./......./......./......./...r.../y..y..."
+        );
+    }
+
+    #[test]
+    fn test_undo_without_moves_is_an_error() {
+        let mut game: Game = Game::default();
+        assert_eq!(game.undo(), Err(HistoryError::NothingToUndo));
+        assert_eq!(game.board(), &GameState::new());
+    }
+
+    #[test]
+    fn test_illegal_move_is_not_recorded() {
+        let mut game: Game = Game::default();
+        assert_eq!(
+            game.play(Move::Drop(9)),
+            Err(HistoryError::IllegalMove(Move::Drop(9)))
+        );
+        assert_eq!(game.ply(), 0);
+    }
+
+    #[test]
+    fn test_repetitions_under_popout() {
+        let mut start: GameState = GameState::from_fen(
+            "......./......./......./......./......./y.....r",
+            Some(crate::color::Color::Yellow),
+        )
+        .unwrap();
+        start.rules.popout = true;
+        let mut game = Game::new(start);
+        for _ in 0..2 {
+            // Both players pop their piece and drop it back in
+            for mv in [Move::Pop(0), Move::Pop(6), Move::Drop(0), Move::Drop(6)] {
+                game.play(mv).unwrap();
+            }
+        }
+        assert_eq!(game.repetitions(), 3);
+        assert_eq!(game.result(), Gameover::Tie);
+        game.undo().unwrap();
+        assert_eq!(game.result(), Gameover::None);
+    }
+}
@@ -1,93 +1,425 @@
 use crate::color::{Color, Gameover};
+use crate::dimensions::{Bitboard, Dimensions, Standard, MAX_WIDTH};
+use crate::rules::Rules;
 use colored::*;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use tinyvec::ArrayVec;
 
 const USE_ICONS: bool = false;
-const DEFAULT_STARTING_PLAYER: Color = Color::Red;
-
-const PIECE_ICON: &'static str = "●";
-const EMPTY_ICON: &'static str = "○";
-
-const RED_PIECE: &'static str = if USE_ICONS { PIECE_ICON } else { "R" };
-const YELLOW_PIECE: &'static str = if USE_ICONS { PIECE_ICON } else { "Y" };
-const EMPTY_PIECE: &'static str = if USE_ICONS { EMPTY_ICON } else { " " };
-const FULL_BOARD_MASK: u64 = 0b_0111111_0111111_0111111_0111111_0111111_0111111_0111111; // 7 bits per column, MSB is sentinel
-
-#[derive(Clone, PartialEq, Eq, Hash)]
-pub struct GameState {
-    // Bitboards for each player, using 7 bits per column (6 rows + 1 sentinel row for overflow)
-    // MS_7bits is the far right column, LS_7bits is the far left column
-    pub red: u64,
-    pub yellow: u64,
+/// Player assumed to have moved first when a FEN doesn't say, matching [`GameState::new`]
+const DEFAULT_STARTING_PLAYER: Color = Color::Yellow;
+
+const PIECE_ICON: &str = "●";
+const EMPTY_ICON: &str = "○";
+
+const RED_PIECE: &str = if USE_ICONS { PIECE_ICON } else { "R" };
+const YELLOW_PIECE: &str = if USE_ICONS { PIECE_ICON } else { "Y" };
+const EMPTY_PIECE: &str = if USE_ICONS { EMPTY_ICON } else { " " };
+const OBSTACLE_PIECE: &str = "#";
+
+/// Directions on a cylinder as (columns, rows) per step: up, right, down-right, up-right
+const CYLINDER_DIRECTIONS: [(u32, i32); 4] = [(0, 1), (1, 0), (1, -1), (1, 1)];
+
+/// Most moves possible in one position: a drop and a pop in every column
+pub const MAX_MOVES: usize = 2 * MAX_WIDTH;
+
+/// A list of moves, big enough for every legal move of any position
+pub type Moves = ArrayVec<[Move; MAX_MOVES]>;
+
+/// A single turn, identified by a 0-indexed column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Move {
+    /// Drop a piece on top of the column
+    Drop(u8),
+    /// Remove your own piece from the bottom of the column, which is only allowed under PopOut rules
+    Pop(u8),
+}
+
+impl Default for Move {
+    fn default() -> Self {
+        Move::Drop(0)
+    }
+}
+
+impl Move {
+    pub const fn column(&self) -> u8 {
+        match self {
+            Move::Drop(column) | Move::Pop(column) => *column,
+        }
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Columns are displayed 1-indexed
+        match self {
+            Move::Drop(_) => write!(f, "column {}", self.column() + 1),
+            Move::Pop(_) => write!(f, "pop column {}", self.column() + 1),
+        }
+    }
+}
+
+/// Why a FEN string could not be turned into a position.
+/// Rows count from 0 at the top, in the order they are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FenError {
+    /// The board has a different number of rows
+    WrongRowCount { expected: u8, found: usize },
+    /// A row has a different number of columns
+    WrongRowLength {
+        row: usize,
+        expected: u8,
+        found: usize,
+    },
+    /// A character that is not `y`, `r`, `#` or `.`
+    UnknownCharacter { row: usize, character: char },
+    /// A piece with an empty cell below it, counting rows from 0 at the bottom
+    FloatingPiece { column: u8, row: u8 },
+    /// One player has more than one piece more than the other
+    PieceCountMismatch { yellow: u32, red: u32 },
+    /// Both players have a line, but the game ends as soon as the first one is made
+    BothConnected,
+    /// Neither just the board nor the board followed by side to move, starting player and ply
+    WrongFieldCount { found: usize },
+    /// The side to move or starting player is not `y` or `r`
+    UnknownColor { field: usize },
+    /// The ply count is not a number
+    InvalidPly,
+    /// The side to move, starting player and ply count don't fit together or with the board
+    TurnMismatch,
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Rows and columns are displayed 1-indexed
+        match self {
+            FenError::WrongRowCount { expected, found } => {
+                write!(f, "expected {} rows but found {}", expected, found)
+            }
+            FenError::WrongRowLength {
+                row,
+                expected,
+                found,
+            } => write!(
+                f,
+                "row {} from the top has {} cells instead of {}",
+                row + 1,
+                found,
+                expected
+            ),
+            FenError::UnknownCharacter { row, character } => write!(
+                f,
+                "row {} from the top contains unknown character '{}'",
+                row + 1,
+                character
+            ),
+            FenError::FloatingPiece { column, row } => write!(
+                f,
+                "the piece in column {}, row {} from the bottom has nothing below it",
+                column + 1,
+                row + 1
+            ),
+            FenError::PieceCountMismatch { yellow, red } => write!(
+                f,
+                "{} has {} pieces and {} has {}, but the players take turns",
+                Color::Yellow,
+                yellow,
+                Color::Red,
+                red
+            ),
+            FenError::BothConnected => write!(f, "both players have a line"),
+            FenError::WrongFieldCount { found } => write!(
+                f,
+                "expected the board alone or followed by 3 fields, but found {} fields",
+                found
+            ),
+            FenError::UnknownColor { field } => write!(f, "field {} should be y or r", field + 1),
+            FenError::InvalidPly => write!(f, "the ply count is not a number"),
+            FenError::TurnMismatch => write!(
+                f,
+                "the side to move, starting player and ply count don't match the board"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+/// State of a position judged from the pieces alone, for positions that didn't come from normal play.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Classification {
+    /// Neither player has a line and there are moves left
+    Ongoing,
+    /// The game is over with this winner, after applying misère rules
+    Win(Color),
+    Tie,
+    /// Both players have a line, which only a pop can bring about
+    BothConnected,
+}
+
+#[derive(Clone)]
+pub struct GameState<D: Dimensions = Standard> {
+    // Bitboards for each player, using HEIGHT + 1 bits per column (HEIGHT rows + 1 sentinel row for overflow)
+    // The most significant column is the far right column, the least significant is the far left column
+    pub red: D::Bits,
+    pub yellow: D::Bits,
+    /// Permanently blocked cells that neither player can occupy, and that pieces land on like a floor
+    pub obstacles: D::Bits,
     pub current_player: Color,
+    pub rules: Rules,
+    /// Number of moves made since the game started, including any made before the position was loaded.
+    /// The player who started follows from it, see [`GameState::starting_player`].
+    ///
+    /// It isn't part of the position, so it is left out when positions are compared or hashed.
+    pub ply: u32,
+}
+
+impl<D: Dimensions> PartialEq for GameState<D> {
+    fn eq(&self, other: &Self) -> bool {
+        self.red == other.red
+            && self.yellow == other.yellow
+            && self.obstacles == other.obstacles
+            && self.current_player == other.current_player
+            && self.rules == other.rules
+    }
+}
+
+impl<D: Dimensions> Eq for GameState<D> {}
+
+impl<D: Dimensions> Hash for GameState<D> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.red.hash(state);
+        self.yellow.hash(state);
+        self.obstacles.hash(state);
+        self.current_player.hash(state);
+        self.rules.hash(state);
+    }
+}
+
+impl<D: Dimensions> Default for GameState<D> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl GameState {
+impl<D: Dimensions> GameState<D> {
     pub fn new() -> Self {
+        Self::with_rules(Rules::default())
+    }
+
+    pub fn with_rules(rules: Rules) -> Self {
         GameState {
-            red: 0,
-            yellow: 0,
+            red: D::Bits::ZERO,
+            yellow: D::Bits::ZERO,
+            obstacles: D::Bits::ZERO,
             current_player: Color::Yellow,
+            rules,
+            ply: 0,
+        }
+    }
+
+    /// The player who made the first move, worked out from the ply count and the player to move
+    pub fn starting_player(&self) -> Color {
+        if self.ply.is_multiple_of(2) {
+            self.current_player
+        } else {
+            self.current_player.opposite()
         }
     }
 
+    /// Change whose turn it is. [`GameState::gameover_state`] assumes the other player made the last move,
+    /// so use [`GameState::classify`] to see whether the position is still playable.
     #[inline(always)]
     pub fn override_current_player(&mut self, color: Color) {
         self.current_player = color;
     }
 
-    pub fn from_fen(s: &str, color: Option<Color>) -> Self {
-        let mut game = GameState::new();
-        let mut row = 5; // Start from the top row
-        let mut col = 0;
+    /// Bitboard with only the cell at `column` and `row` set, where row 0 is the bottom row.
+    #[inline(always)]
+    pub fn cell_mask(column: u8, row: u8) -> D::Bits {
+        D::Bits::ONE << (column as u32 * D::STRIDE as u32 + row as u32)
+    }
 
-        for c in s.chars() {
-            match c {
-                'r' => {
-                    let bit_index = col * 7 + row;
-                    game.red |= 1u64 << bit_index;
-                    col += 1;
-                }
-                'y' => {
-                    let bit_index = col * 7 + row;
-                    game.yellow |= 1u64 << bit_index;
-                    col += 1;
-                }
-                '.' => col += 1,
-                '/' => {
-                    row -= 1;
-                    col = 0;
+    /// Parse a position written from the top row down, with rows separated by `/`:
+    /// `y` and `r` for pieces, `#` for obstacles and `.` for empty cells.
+    ///
+    /// The board may be followed by the side to move, the player who started and the ply count,
+    /// as written by [`GameState::to_fen`], e.g. `......./......./......./......./......./...y... r y 1`.
+    /// Without them, the player to move is worked out from the piece counts, assuming Yellow started.
+    /// `color`, if given, overrides the player to move either way.
+    ///
+    /// The position is checked under the default rules, see [`GameState::from_fen_with_rules`] for other rules.
+    pub fn from_fen(s: &str, color: Option<Color>) -> Result<Self, FenError> {
+        Self::from_fen_with_rules(s, color, Rules::default())
+    }
+
+    /// Like [`GameState::from_fen`], but the position is checked and played under `rules`.
+    ///
+    /// Under PopOut rules the piece counts can drift apart, so they aren't checked.
+    /// Whether both players have a line depends on the connect length and the cylinder rule.
+    pub fn from_fen_with_rules(
+        s: &str,
+        color: Option<Color>,
+        rules: Rules,
+    ) -> Result<Self, FenError> {
+        let mut game = Self::with_rules(rules);
+
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let (board, turn) = match fields[..] {
+            [board] => (board, None),
+            [board, to_move, starter, ply] => (board, Some((to_move, starter, ply))),
+            _ => {
+                return Err(FenError::WrongFieldCount {
+                    found: fields.len(),
+                })
+            }
+        };
+
+        let rows: Vec<&str> = board.split('/').collect();
+        if rows.len() != D::HEIGHT as usize {
+            return Err(FenError::WrongRowCount {
+                expected: D::HEIGHT,
+                found: rows.len(),
+            });
+        }
+        for (index, text) in rows.iter().enumerate() {
+            let row = D::HEIGHT - 1 - index as u8; // Start from the top row
+            let length = text.chars().count();
+            if length != D::WIDTH as usize {
+                return Err(FenError::WrongRowLength {
+                    row: index,
+                    expected: D::WIDTH,
+                    found: length,
+                });
+            }
+            for (col, c) in text.chars().enumerate() {
+                let mask = Self::cell_mask(col as u8, row);
+                match c {
+                    'r' => game.red |= mask,
+                    'y' => game.yellow |= mask,
+                    '#' => game.obstacles |= mask,
+                    '.' => {}
+                    _ => {
+                        return Err(FenError::UnknownCharacter {
+                            row: index,
+                            character: c,
+                        })
+                    }
                 }
-                _ => continue,
             }
         }
 
+        if let Some((column, row)) = game.floating_piece() {
+            return Err(FenError::FloatingPiece { column, row });
+        }
+
         // Count pieces to determine the current player
         let red_count = game.red.count_ones();
         let yellow_count = game.yellow.count_ones();
+        // A pop takes away one of the popping player's pieces, so one player can get far ahead
+        if !rules.popout && red_count.abs_diff(yellow_count) > 1 {
+            return Err(FenError::PieceCountMismatch {
+                yellow: yellow_count,
+                red: red_count,
+            });
+        }
+
+        let pieces = red_count + yellow_count;
+        (game.current_player, game.ply) = match turn {
+            Some((to_move, starter, ply)) => {
+                let to_move = Self::parse_color(to_move, 1)?;
+                let starter = Self::parse_color(starter, 2)?;
+                let ply: u32 = ply.parse().map_err(|_| FenError::InvalidPly)?;
+                let expected = if ply.is_multiple_of(2) {
+                    starter
+                } else {
+                    starter.opposite()
+                };
+                // Every move is a drop or a pop, so each one changes the piece count by exactly one,
+                // and without pops every move adds a piece, with the starter a piece ahead after an odd ply
+                let plies_fit = if rules.popout {
+                    ply >= pieces && (ply - pieces).is_multiple_of(2)
+                } else {
+                    ply == pieces
+                        && game.pieces(starter).count_ones() == ply.div_ceil(2)
+                        && game.pieces(starter.opposite()).count_ones() == ply / 2
+                };
+                if to_move != expected || !plies_fit {
+                    return Err(FenError::TurnMismatch);
+                }
+                (to_move, ply)
+            }
+            None if game.pieces(DEFAULT_STARTING_PLAYER).count_ones()
+                > game.pieces(DEFAULT_STARTING_PLAYER.opposite()).count_ones() =>
+            {
+                (DEFAULT_STARTING_PLAYER.opposite(), pieces)
+            }
+            // Equal piece counts, or the other player started
+            None => (DEFAULT_STARTING_PLAYER, pieces),
+        };
         if let Some(c) = color {
             game.current_player = c;
-        } else {
-            game.current_player = if red_count == yellow_count {
-                DEFAULT_STARTING_PLAYER
-            } else {
-                DEFAULT_STARTING_PLAYER.opposite()
-            };
         }
 
-        game
+        // Under PopOut rules this is a win for whoever popped, which depends on the player to move
+        if game.classify() == Classification::BothConnected {
+            return Err(FenError::BothConnected);
+        }
+
+        Ok(game)
+    }
+
+    /// Column and row of the lowest, leftmost piece that doesn't rest on the bottom row,
+    /// another piece or an obstacle, if there is one.
+    pub(crate) fn floating_piece(&self) -> Option<(u8, u8)> {
+        let full_board = D::Bits::from_u128(D::FULL_BOARD_MASK);
+        let bottom_row = full_board & !(full_board << 1);
+        let floating = self.filled() & !((self.occupied() << 1) | bottom_row);
+        if floating == D::Bits::ZERO {
+            return None;
+        }
+        let index = floating.trailing_zeros();
+        Some((
+            (index / D::STRIDE as u32) as u8,
+            (index % D::STRIDE as u32) as u8,
+        ))
+    }
+
+    /// Parse the side to move or starting player, which is the `field`th field of a FEN string
+    fn parse_color(text: &str, field: usize) -> Result<Color, FenError> {
+        let mut chars = text.chars();
+        match (chars.next().and_then(Color::from_fen_char), chars.next()) {
+            (Some(color), None) => Ok(color),
+            _ => Err(FenError::UnknownColor { field }),
+        }
     }
 
+    /// Write the position as the board followed by the side to move, the player who started and the ply count,
+    /// which [`GameState::from_fen`] reads back.
     pub fn to_fen(&self) -> String {
+        format!(
+            "{} {} {} {}",
+            self.board_fen(),
+            self.current_player.fen_char(),
+            self.starting_player().fen_char(),
+            self.ply
+        )
+    }
+
+    /// Write only the pieces and obstacles, as in the legacy FEN without the turn fields
+    pub fn board_fen(&self) -> String {
         let mut result = String::new();
-        for row in (0..6).rev() {
-            for col in 0..7 {
-                let bit_index = col * 7 + row;
-                let mask = 1u64 << bit_index;
-                result.push(if self.red & mask != 0 {
+        for row in (0..D::HEIGHT).rev() {
+            for col in 0..D::WIDTH {
+                let mask = Self::cell_mask(col, row);
+                result.push(if self.red & mask != D::Bits::ZERO {
                     'r'
-                } else if self.yellow & mask != 0 {
+                } else if self.yellow & mask != D::Bits::ZERO {
                     'y'
+                } else if self.obstacles & mask != D::Bits::ZERO {
+                    '#'
                 } else {
                     '.'
                 });
@@ -100,63 +432,202 @@ impl GameState {
     }
 
     pub fn gameover_state(&self) -> Gameover {
-        let piece_count = (self.filled() & FULL_BOARD_MASK).count_ones();
-        // Only check for gameover if there are at least 7 pieces on the board
+        let top_row = D::Bits::from_u128(D::TOP_ROW_MASK);
+        let piece_count = (self.filled() & D::Bits::from_u128(D::FULL_BOARD_MASK)).count_ones();
+        // Only check for a win if there are enough pieces on the board, e.g. 7 when playing Connect Four
         // This is assuming normal gameplay where players alternate turns
-        // With only 6 pieces, no player can win
-        if piece_count < 7 {
-            return Gameover::None;
+        // With any fewer pieces, no player can win
+        // Pops take pieces off the board, so the piece count says nothing under PopOut rules
+        if self.rules.popout || piece_count >= self.rules.min_pieces_for_win() {
+            let previous_player = self.current_player.opposite();
+            // Without pops, the only valid gameover state is if the non-current player has a line.
+            // A pop can complete a line for the opponent, in which case they have a line.
+            // If it completes lines for both players, the line of the player who popped counts, as checked first.
+            let line = if Self::has_won(&self.rules, self.pieces(previous_player)) {
+                Some(previous_player)
+            } else if self.rules.popout
+                && Self::has_won(&self.rules, self.pieces(self.current_player))
+            {
+                Some(self.current_player)
+            } else {
+                None
+            };
+            if let Some(color) = line {
+                return Gameover::Win(self.rules.winner_for_line(color));
+            }
         }
 
-        // The only valid gameover state is if the non-current player has won
-        match self.current_player {
-            Color::Yellow => {
-                if Self::has_won(self.red) {
-                    return Gameover::Win(Color::Red);
+        if self.rules.popout {
+            // A full board can still be played on by popping, so the game is only tied when no move is left.
+            // Repeating a position also ties the game, which the game loop has to check since it needs the history.
+            if self.legal_moves().is_empty() {
+                Gameover::Tie
+            } else {
+                Gameover::None
+            }
+        } else if self.occupied() & top_row == top_row {
+            // The game is tied once every column is filled to the top.
+            // Without obstacles that means every cell is filled.
+            Gameover::Tie
+        } else {
+            Gameover::None
+        }
+    }
+
+    /// Like [`GameState::gameover_state`], but checks both players and the whole board.
+    ///
+    /// `gameover_state` assumes the position came from normal play: only the player who just moved
+    /// can have made a line, and nobody can have won with too few pieces on the board.
+    /// Positions that were loaded or had their player to move changed need this instead.
+    pub fn classify(&self) -> Classification {
+        let yellow_line = self.has_line(Color::Yellow);
+        let red_line = self.has_line(Color::Red);
+        let line = match (yellow_line, red_line) {
+            // A pop that completes both lines wins for the player who popped
+            (true, true) if self.rules.popout => Some(self.current_player.opposite()),
+            (true, true) => return Classification::BothConnected,
+            (true, false) => Some(Color::Yellow),
+            (false, true) => Some(Color::Red),
+            (false, false) => None,
+        };
+        if let Some(color) = line {
+            return Classification::Win(self.rules.winner_for_line(color));
+        }
+
+        match self.gameover_state() {
+            Gameover::Tie => Classification::Tie,
+            _ => Classification::Ongoing,
+        }
+    }
+
+    #[inline(always)]
+    /// Bitboard win detection for a single player's board, under the given `rules`.
+    pub(crate) fn has_won(rules: &Rules, board: D::Bits) -> bool {
+        let connect = rules.connect as u32;
+        if rules.cylinder {
+            CYLINDER_DIRECTIONS.iter().any(|&(columns, rows)| {
+                Self::cylinder_line_starts(board, columns, rows, connect) != D::Bits::ZERO
+            })
+        } else {
+            Self::directions()
+                .iter()
+                .any(|&dir| Self::line_starts(board, dir, connect) != D::Bits::ZERO)
+        }
+    }
+
+    /// Every cell that is part of a completed line, for either player.
+    /// Empty unless someone has won, or both players have lines after a pop.
+    pub fn winning_cells(&self) -> D::Bits {
+        Self::line_cells(&self.rules, self.yellow) | Self::line_cells(&self.rules, self.red)
+    }
+
+    /// Cells of `board` that are part of a line of `rules.connect` pieces
+    fn line_cells(rules: &Rules, board: D::Bits) -> D::Bits {
+        let connect = rules.connect as u32;
+        let mut cells = D::Bits::ZERO;
+        if rules.cylinder {
+            for (columns, rows) in CYLINDER_DIRECTIONS {
+                let starts = Self::cylinder_line_starts(board, columns, rows, connect);
+                if starts == D::Bits::ZERO {
+                    continue;
+                }
+                // Walk each line back from its first cell, which is a shift the other way around the board
+                for step in 0..connect {
+                    let back = D::WIDTH as u32 - (columns * step) % D::WIDTH as u32;
+                    cells |= Self::wrapping_shift(starts, back, -rows * step as i32);
                 }
             }
-            Color::Red => {
-                if Self::has_won(self.yellow) {
-                    return Gameover::Win(Color::Yellow);
+        } else {
+            for dir in Self::directions() {
+                let starts = Self::line_starts(board, dir, connect);
+                if starts == D::Bits::ZERO {
+                    continue;
+                }
+                for step in 0..connect {
+                    cells |= starts << (dir * step);
                 }
             }
         }
+        cells
+    }
 
-        // 6 rows * 7 columns = 42 total pieces
-        if piece_count == 42 {
-            Gameover::Tie
-        } else {
-            Gameover::None
-        }
+    /// Bit offsets between neighbouring cells: up, right, down-right and up-right
+    #[inline(always)]
+    const fn directions() -> [u32; 4] {
+        let stride = D::STRIDE as u32;
+        [1, stride, stride - 1, stride + 1]
     }
 
+    /// Bits marking the first cell of every line of `connect` pieces on `board`,
+    /// where each cell of a line is `dir` bits above the one before it.
     #[inline(always)]
-    /// Bitboard win detection for a single player's board.
-    fn has_won(board: u64) -> bool {
-        // Directions: right (1), down (7), down-right (6), down-left (8)
-        const DIRECTIONS: [u8; 4] = [1, 7, 6, 8];
-        for dir in DIRECTIONS {
-            let m1 = board & (board >> dir);
-            let m2 = m1 & (m1 >> (dir * 2));
-            if m2 != 0 {
-                return true;
+    fn line_starts(board: D::Bits, dir: u32, connect: u32) -> D::Bits {
+        // Shifting by the whole board or more would leave nothing behind
+        let board_bits = D::WIDTH as u32 * D::STRIDE as u32;
+
+        // Double the length each step, then top it up to exactly `connect`.
+        let mut runs = board;
+        let mut length = 1;
+        while length < connect {
+            let step = length.min(connect - length);
+            if dir * step >= board_bits {
+                return D::Bits::ZERO;
             }
+            runs &= runs >> (dir * step);
+            length += step;
+        }
+        runs
+    }
+
+    /// Same as [`GameState::line_starts`] for a board whose left and right edges are joined.
+    /// The sentinel rows can't stop lines from wrapping here, so the columns are rotated instead of shifted.
+    fn cylinder_line_starts(board: D::Bits, columns: u32, rows: i32, connect: u32) -> D::Bits {
+        if rows == 0 && connect > D::WIDTH as u32 {
+            // A horizontal line this long would have to go all the way around and reuse its own cells
+            return D::Bits::ZERO;
+        }
+        let mut runs = board;
+        let mut length = 1;
+        while length < connect {
+            let step = length.min(connect - length);
+            runs &= Self::wrapping_shift(runs, columns * step, rows * step as i32);
+            length += step;
         }
+        runs
+    }
 
-        false
+    /// Move the piece at (`column + columns`, `row + rows`) to (`column`, `row`), wrapping around the columns.
+    /// Pieces shifted off the top or bottom of a column are dropped.
+    fn wrapping_shift(board: D::Bits, columns: u32, rows: i32) -> D::Bits {
+        let full_board = D::Bits::from_u128(D::FULL_BOARD_MASK);
+        let board_bits = D::WIDTH as u32 * D::STRIDE as u32;
+        let split = (columns % D::WIDTH as u32) * D::STRIDE as u32;
+
+        let mut shifted = if split == 0 {
+            board
+        } else {
+            ((board >> split) | (board << (board_bits - split))) & full_board
+        };
+        // One row at a time, so pieces leaving a column land in the sentinel row and get masked off
+        for _ in 0..rows.unsigned_abs() {
+            shifted = if rows > 0 { shifted >> 1 } else { shifted << 1 } & full_board;
+        }
+        shifted
     }
 
     /// Make a move in the specified column.
     ///
     /// Returns `true` if the move was successful, `false` if the column is full.
     pub fn make_move(&mut self, column: u8) -> bool {
+        if column >= D::WIDTH {
+            return false; // Not on the board
+        }
         let height = self.get_height(column);
-        if height >= 6 {
+        if height >= D::HEIGHT {
             return false; // Column is full
         }
 
-        let bit_index = column * 7 + height;
-        let mask = 1u64 << bit_index;
+        let mask = Self::cell_mask(column, height);
 
         // place the piece in the appropriate player's bitboard
         match self.current_player {
@@ -169,95 +640,236 @@ impl GameState {
                 self.current_player = Color::Yellow;
             }
         }
+        self.ply += 1;
         true // The move was successful
     }
 
     /// Undo a move made at a specific column
     pub fn undo_move(&mut self, column: u8) {
-        let bit_index = column * 7 + self.get_height(column) - 1;
-        let mask = 1u64 << bit_index;
+        let mask = !Self::cell_mask(column, self.get_height(column) - 1);
         match self.current_player {
             Color::Yellow => {
-                self.red &= !mask;
+                self.red &= mask;
                 self.current_player = Color::Red;
             }
             Color::Red => {
-                self.yellow &= !mask;
+                self.yellow &= mask;
                 self.current_player = Color::Yellow;
             }
         }
+        // Positions built straight from bitboards don't know how many moves led to them
+        self.ply = self.ply.saturating_sub(1);
+    }
+
+    /// Play a drop or a pop.
+    ///
+    /// Returns `true` if the move was successful, `false` if it was not legal.
+    pub fn play(&mut self, mv: Move) -> bool {
+        match mv {
+            Move::Drop(column) => self.make_move(column),
+            Move::Pop(column) => self.pop(column),
+        }
+    }
+
+    /// Undo a drop or pop that was just played
+    pub fn unplay(&mut self, mv: Move) {
+        match mv {
+            Move::Drop(column) => self.undo_move(column),
+            Move::Pop(column) => self.undo_pop(column),
+        }
+    }
+
+    /// Remove the current player's piece from the bottom of `column`, letting the pieces above it fall down.
+    ///
+    /// Returns `true` if the pop was successful, `false` if it is not allowed.
+    pub fn pop(&mut self, column: u8) -> bool {
+        if !self.can_pop(column) {
+            return false;
+        }
+
+        self.red = Self::shift_column(self.red, column, |bits| bits >> 1);
+        self.yellow = Self::shift_column(self.yellow, column, |bits| bits >> 1);
+        self.current_player = self.current_player.opposite();
+        self.ply += 1;
+        true
+    }
+
+    /// Undo a pop made at a specific column
+    pub fn undo_pop(&mut self, column: u8) {
+        self.red = Self::shift_column(self.red, column, |bits| bits << 1);
+        self.yellow = Self::shift_column(self.yellow, column, |bits| bits << 1);
+        self.current_player = self.current_player.opposite();
+        self.ply = self.ply.saturating_sub(1);
+        let bottom = Self::cell_mask(column, 0);
+        match self.current_player {
+            Color::Yellow => self.yellow |= bottom,
+            Color::Red => self.red |= bottom,
+        }
+    }
+
+    /// Apply `shift` to the pieces of a single column, leaving the other columns alone.
+    fn shift_column(board: D::Bits, column: u8, shift: impl Fn(D::Bits) -> D::Bits) -> D::Bits {
+        let offset = column as u32 * D::STRIDE as u32;
+        let column_mask = D::Bits::from_u128(D::COLUMN_MASK);
+        let pieces = (board >> offset) & column_mask;
+        (board & !(column_mask << offset)) | ((shift(pieces) & column_mask) << offset)
+    }
+
+    /// Whether the current player may pop from the bottom of `column`.
+    /// Obstacles never move, so columns containing one can't be popped.
+    #[inline(always)]
+    pub fn can_pop(&self, column: u8) -> bool {
+        if !self.rules.popout || column >= D::WIDTH {
+            return false;
+        }
+        let column_mask = D::Bits::from_u128(D::COLUMN_MASK) << (column as u32 * D::STRIDE as u32);
+        self.pieces(self.current_player) & Self::cell_mask(column, 0) != D::Bits::ZERO
+            && self.obstacles & column_mask == D::Bits::ZERO
+    }
+
+    /// Bitboard of the pieces belonging to `color`
+    #[inline(always)]
+    pub fn pieces(&self, color: Color) -> D::Bits {
+        match color {
+            Color::Yellow => self.yellow,
+            Color::Red => self.red,
+        }
+    }
+
+    /// Whether `color` has enough pieces in a row to win, ignoring whose turn it is
+    pub fn has_line(&self, color: Color) -> bool {
+        Self::has_won(&self.rules, self.pieces(color))
     }
 
     #[inline(always)]
     pub fn get_height(&self, column: u8) -> u8 {
-        const LS_SIX_BITS: u64 = 0b111111;
-        let col_bits = (self.filled() >> (column * 7)) & LS_SIX_BITS;
-        col_bits.trailing_ones() as u8
+        // Pieces land on top of the highest occupied cell, which may be an obstacle with empty cells below it
+        let col_bits = (self.occupied() >> (column as u32 * D::STRIDE as u32))
+            & D::Bits::from_u128(D::COLUMN_MASK);
+        (D::Bits::BITS - col_bits.leading_zeros()) as u8
+    }
+
+    /// Whether a piece can still be dropped into `column`
+    #[inline(always)]
+    pub fn can_play(&self, column: u8) -> bool {
+        self.get_height(column) < D::HEIGHT
+    }
+
+    /// Every legal move, with the center columns first and drops before pops
+    pub fn legal_moves(&self) -> Moves {
+        let columns = D::column_order();
+        let drops = columns
+            .iter()
+            .filter(|&&column| self.can_play(column))
+            .map(|&column| Move::Drop(column));
+        let pops = columns
+            .iter()
+            .filter(|&&column| self.can_pop(column))
+            .map(|&column| Move::Pop(column));
+        drops.chain(pops).collect()
     }
 
     #[inline(always)]
-    pub fn filled(&self) -> u64 {
+    pub fn filled(&self) -> D::Bits {
         self.red | self.yellow
     }
+
+    /// Every cell that can't be played into, whether it holds a piece or an obstacle
+    #[inline(always)]
+    pub fn occupied(&self) -> D::Bits {
+        self.filled() | self.obstacles
+    }
 }
 
-impl fmt::Debug for GameState {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "{:} to play", self.current_player)?;
+impl<D: Dimensions> GameState<D> {
+    /// Symbol for the cell at `mask`, coloured by whoever is in it
+    pub(crate) fn piece_at(&self, mask: D::Bits) -> ColoredString {
+        if self.red & mask != D::Bits::ZERO {
+            RED_PIECE.red()
+        } else if self.yellow & mask != D::Bits::ZERO {
+            YELLOW_PIECE.yellow()
+        } else if self.obstacles & mask != D::Bits::ZERO {
+            OBSTACLE_PIECE.bright_black()
+        } else {
+            EMPTY_PIECE.white()
+        }
+    }
+}
 
-        for row in (0..6).rev() {
-            // Print from top to bottom
-            write!(f, "|")?;
-            for col in 0..7 {
-                let bit_index = col * 7 + row;
-                let mask = 1u64 << bit_index;
-                write!(
-                    f,
-                    " {} ",
-                    if self.red & mask != 0 {
-                        RED_PIECE.red()
-                    } else if self.yellow & mask != 0 {
-                        YELLOW_PIECE.yellow()
-                    } else {
-                        EMPTY_PIECE.white()
-                    }
-                )?;
+/// Draw a `D` board from the top row down, followed by the column numbers.
+/// `piece` gives the symbol for the cell with the given mask, and the cells in `highlighted` are drawn in brackets.
+/// Variants with their own pieces draw their boards with this too, so every board looks the same.
+pub(crate) fn write_board<D: Dimensions>(
+    f: &mut fmt::Formatter<'_>,
+    cylinder: bool,
+    highlighted: D::Bits,
+    piece: impl Fn(D::Bits) -> ColoredString,
+) -> fmt::Result {
+    // Dotted edges show that the first and last columns are joined
+    let edge = if cylinder { ":" } else { "|" };
+    for row in (0..D::HEIGHT).rev() {
+        // Print from top to bottom
+        write!(f, "{}", edge)?;
+        for col in 0..D::WIDTH {
+            let mask = GameState::<D>::cell_mask(col, row);
+            if highlighted & mask != D::Bits::ZERO {
+                // Brackets show the line even when colors are turned off
+                write!(f, "[{}]", piece(mask).bold().reversed())?;
+            } else {
+                write!(f, " {} ", piece(mask))?;
             }
-            writeln!(f, "|")?;
         }
+        writeln!(f, "{}", edge)?;
+    }
 
-        writeln!(f, "  1  2  3  4  5  6  7")?; // Column indices
+    // Column indices
+    for col in 1..=D::WIDTH {
+        write!(f, "{:>3}", col)?;
+    }
+    writeln!(f)
+}
+
+impl<D: Dimensions> fmt::Debug for GameState<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:} to play", self.current_player)?;
+        write_board::<D>(f, self.rules.cylinder, self.winning_cells(), |mask| {
+            self.piece_at(mask)
+        })?;
         writeln!(f, "\n{}", self.to_fen()) // Print FEN representation
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::gamestate::{GameState, Gameover};
+    use crate::color::Color;
+    use crate::dimensions::{Dims, Standard, WideDims};
+    use crate::gamestate::{Classification, FenError, GameState, Gameover, Move};
+    use crate::rules::Rules;
 
     #[test]
     fn test_game_not_over_empty_board() {
-        let game = GameState::new();
+        let game: GameState = GameState::new();
         assert_eq!(game.gameover_state(), Gameover::None);
     }
 
     #[test]
     fn test_tie_full_board_no_winner() {
-        let game = GameState::from_fen("yrryyry/ryrrryr/rryyyrr/yyyrryy/rryyyry/yyrrryr", None);
+        let game: GameState =
+            GameState::from_fen("yrryyry/ryrrryr/rryyyrr/yyyrryy/rryyyry/yyrrryr", None).unwrap();
         println!("{:?}", game);
         assert_eq!(game.gameover_state(), Gameover::Tie);
     }
 
     #[test]
     fn test_game_in_progress_partial_board() {
-        let mut game = GameState::new();
+        let mut game: GameState = GameState::new();
         game.red = (1 << 0) | (1 << 7); // two red moves
         game.yellow = 1 << 1; // one yellow move
         assert_eq!(game.gameover_state(), Gameover::None);
     }
 
     fn game_with_column(column: u8, pieces: u8) -> GameState {
-        let mut game = GameState::new();
+        let mut game: GameState = GameState::new();
         for _ in 0..pieces {
             game.make_move(column);
         }
@@ -266,7 +878,7 @@ mod tests {
 
     #[test]
     fn test_empty_column() {
-        let game = GameState::new();
+        let game: GameState = GameState::new();
         assert_eq!(game.get_height(0), 0);
         assert_eq!(game.get_height(3), 0);
     }
@@ -291,7 +903,7 @@ mod tests {
 
     #[test]
     fn test_mixed_columns() {
-        let mut game = GameState::new();
+        let mut game: GameState = GameState::new();
         game.make_move(0); // Y
         game.make_move(1); // R
         game.make_move(0); // Y
@@ -302,4 +914,566 @@ mod tests {
         assert_eq!(game.get_height(1), 2);
         assert_eq!(game.get_height(2), 0);
     }
+
+    #[test]
+    fn test_tie_full_small_board() {
+        let game =
+            GameState::<Dims<6, 5>>::from_fen("rryyrr/yyryry/ryryyr/ryyrrr/yryryy", None).unwrap();
+        assert_eq!(game.gameover_state(), Gameover::Tie);
+    }
+
+    #[test]
+    fn test_win_on_right_edge_of_large_board() {
+        // Yellow fills the bottom row of the four rightmost columns of an 8x7 board
+        let mut game = GameState::<Dims<8, 7>>::new();
+        for column in [4, 0, 5, 0, 6, 1, 7] {
+            game.make_move(column);
+        }
+        assert_eq!(game.gameover_state(), Gameover::Win(Color::Yellow));
+    }
+
+    #[test]
+    fn test_diagonal_win_on_wide_board() {
+        let game = GameState::<WideDims<9, 7>>::from_fen(
+            "........./........./........./.....y.../....yr.../...yrr.../..yrrry..",
+            Some(Color::Red),
+        )
+        .unwrap();
+        assert_eq!(game.gameover_state(), Gameover::Win(Color::Yellow));
+    }
+
+    #[test]
+    fn test_no_wrap_between_columns_on_wide_board() {
+        // Three in the top of column 1 and one at the bottom of column 2 must not join up
+        let game = GameState::<WideDims<9, 7>>::from_fen(
+            "r......../r......../r......../y......../y......../y......../yr.......",
+            Some(Color::Yellow),
+        )
+        .unwrap();
+        assert_eq!(game.gameover_state(), Gameover::None);
+    }
+
+    #[test]
+    fn test_fen_round_trip_on_other_sizes() {
+        let fen = "......../......../......../......../...r..../...yr.../..yyr...";
+        assert_eq!(
+            GameState::<Dims<8, 7>>::from_fen(fen, None)
+                .unwrap()
+                .board_fen(),
+            fen
+        );
+        let fen = "........./........./........./........./........./..r....../..yy.r..y";
+        assert_eq!(
+            GameState::<WideDims<9, 7>>::from_fen(fen, None)
+                .unwrap()
+                .board_fen(),
+            fen
+        );
+    }
+
+    #[test]
+    fn test_extended_fen_round_trip() {
+        let mut game: GameState = GameState::new();
+        for column in [3, 3, 4] {
+            game.make_move(column);
+        }
+        let fen = game.to_fen();
+        assert_eq!(fen, "......./......./......./......./...r.../...yy.. r y 3");
+        assert_eq!(GameState::from_fen(&fen, None).unwrap(), game);
+
+        // Red started, so Red is to move again after an even number of plies
+        let fen = "......./......./......./......./......./...ry.. r r 2";
+        let game: GameState = GameState::from_fen(fen, None).unwrap();
+        assert_eq!(game.current_player, Color::Red);
+        assert_eq!(game.to_fen(), fen);
+    }
+
+    #[test]
+    fn test_extended_fen_round_trip_after_pops() {
+        let mut game: GameState = GameState::with_rules(Rules {
+            popout: true,
+            ..Rules::default()
+        });
+        for mv in [
+            Move::Drop(3),
+            Move::Drop(4),
+            Move::Drop(3),
+            Move::Drop(4),
+            Move::Pop(3),
+            Move::Drop(4),
+            Move::Pop(3),
+        ] {
+            assert!(game.play(mv));
+        }
+        let fen = game.to_fen();
+        assert_eq!(fen, "......./......./......./....r../....r../....r.. r y 7");
+        let loaded: GameState = GameState::from_fen_with_rules(&fen, None, game.rules).unwrap();
+        assert_eq!(loaded.ply, 7);
+        assert_eq!(loaded.to_fen(), fen);
+
+        // Red started and made the last of three moves, one of them a pop
+        let fen = "......./......./......./......./......./...r... y r 3";
+        let loaded: GameState = GameState::from_fen_with_rules(fen, None, game.rules).unwrap();
+        assert_eq!(loaded.starting_player(), Color::Red);
+        assert_eq!(loaded.to_fen(), fen);
+    }
+
+    #[test]
+    fn test_legacy_fen_assumes_yellow_started() {
+        let game: GameState =
+            GameState::from_fen("......./......./......./......./......./...ry..", None).unwrap();
+        assert_eq!(game.current_player, Color::Yellow);
+        let game: GameState =
+            GameState::from_fen("......./......./......./......./......./...r...", None).unwrap();
+        assert_eq!(game.current_player, Color::Yellow);
+        let game: GameState =
+            GameState::from_fen("......./......./......./......./......./...y...", None).unwrap();
+        assert_eq!(game.current_player, Color::Red);
+    }
+
+    #[test]
+    fn test_extended_fen_rejects_bad_turn_fields() {
+        let parse = |turn| {
+            let fen = format!("......./......./......./......./......./...y... {}", turn);
+            GameState::<Standard>::from_fen(&fen, None).map(|_| ())
+        };
+        assert_eq!(parse("r"), Err(FenError::WrongFieldCount { found: 2 }));
+        assert_eq!(parse("r g 1"), Err(FenError::UnknownColor { field: 2 }));
+        assert_eq!(parse("r y one"), Err(FenError::InvalidPly));
+        // Yellow started and one move was made, so it must be Red's turn
+        assert_eq!(parse("y y 1"), Err(FenError::TurnMismatch));
+        // Fewer plies than pieces on the board
+        assert_eq!(parse("y y 0"), Err(FenError::TurnMismatch));
+        // Only pops can make the game longer than the piece count
+        assert_eq!(parse("r y 3"), Err(FenError::TurnMismatch));
+        // Red started, so the only piece after one move should be Red's
+        assert_eq!(parse("y r 1"), Err(FenError::TurnMismatch));
+        let popout = Rules {
+            popout: true,
+            ..Rules::default()
+        };
+        let fen = "......./......./......./......./......./...y... r y 3";
+        assert!(GameState::<Standard>::from_fen_with_rules(fen, None, popout).is_ok());
+    }
+
+    #[test]
+    fn test_fen_rejects_malformed_boards() {
+        let parse = |fen| GameState::<Standard>::from_fen(fen, None).map(|_| ());
+        assert_eq!(
+            parse("......./......./......./......./......."),
+            Err(FenError::WrongRowCount {
+                expected: 6,
+                found: 5
+            })
+        );
+        assert_eq!(
+            parse("......./......./......../......./......./......."),
+            Err(FenError::WrongRowLength {
+                row: 2,
+                expected: 7,
+                found: 8
+            })
+        );
+        assert_eq!(
+            parse("......./......./......./......./......./...x..."),
+            Err(FenError::UnknownCharacter {
+                row: 5,
+                character: 'x'
+            })
+        );
+    }
+
+    #[test]
+    fn test_fen_rejects_impossible_positions() {
+        let parse = |fen| GameState::<Standard>::from_fen(fen, None).map(|_| ());
+        assert_eq!(
+            parse("......./......./......./......./...y.../..r...."),
+            Err(FenError::FloatingPiece { column: 3, row: 1 })
+        );
+        assert_eq!(
+            parse("......./......./......./......./......./yyy...."),
+            Err(FenError::PieceCountMismatch { yellow: 3, red: 0 })
+        );
+        assert_eq!(
+            parse("......./......./y.....r/y.....r/y.....r/y.....r"),
+            Err(FenError::BothConnected)
+        );
+        // Pieces may rest on obstacles
+        assert!(parse("......./......./......./...y.../...#.../..r....").is_ok());
+    }
+
+    #[test]
+    fn test_fen_is_checked_under_its_rules() {
+        let popout = Rules {
+            popout: true,
+            ..Rules::default()
+        };
+        // Yellow pops both their pieces back out of column 4 while Red keeps dropping into column 5
+        let mut played = GameState::<Standard>::with_rules(popout);
+        for mv in [
+            Move::Drop(3),
+            Move::Drop(4),
+            Move::Drop(3),
+            Move::Drop(4),
+            Move::Pop(3),
+            Move::Drop(4),
+            Move::Pop(3),
+        ] {
+            assert!(played.play(mv));
+        }
+        let fen = played.board_fen();
+        assert_eq!(fen, "......./......./......./....r../....r../....r..");
+        assert_eq!(
+            GameState::<Standard>::from_fen(&fen, Some(Color::Red)),
+            Err(FenError::PieceCountMismatch { yellow: 0, red: 3 })
+        );
+        let game =
+            GameState::<Standard>::from_fen_with_rules(&fen, Some(Color::Red), popout).unwrap();
+        assert_eq!(game, played);
+
+        // Two rows of three only connect both players in Connect-3
+        let fen = "......./......./......./......./rrr..../yyy...y";
+        assert!(GameState::<Standard>::from_fen(fen, None).is_ok());
+        let connect_three = Rules {
+            connect: 3,
+            ..Rules::default()
+        };
+        assert_eq!(
+            GameState::<Standard>::from_fen_with_rules(fen, None, connect_three),
+            Err(FenError::BothConnected)
+        );
+
+        // The rows only join up on a cylinder
+        let fen = "......./......./......./......./rr...rr/yy...yy";
+        let cylinder = Rules {
+            cylinder: true,
+            ..Rules::default()
+        };
+        assert!(GameState::<Standard>::from_fen(fen, Some(Color::Yellow)).is_ok());
+        assert_eq!(
+            GameState::<Standard>::from_fen_with_rules(fen, Some(Color::Yellow), cylinder),
+            Err(FenError::BothConnected)
+        );
+    }
+
+    #[test]
+    fn test_full_column_on_small_board() {
+        let mut game = GameState::<Dims<6, 5>>::new();
+        for _ in 0..5 {
+            assert!(game.make_move(2));
+        }
+        assert!(!game.can_play(2));
+        assert!(!game.make_move(2));
+        let columns: Vec<u8> = game.legal_moves().iter().map(Move::column).collect();
+        assert_eq!(columns, [3, 1, 4, 0, 5]);
+    }
+
+    #[test]
+    fn test_connect_three() {
+        let mut game: GameState = GameState::with_rules(Rules {
+            connect: 3,
+            ..Rules::default()
+        });
+        for column in [0, 0, 1, 1] {
+            game.make_move(column);
+        }
+        assert_eq!(game.gameover_state(), Gameover::None);
+        game.make_move(2);
+        assert_eq!(game.gameover_state(), Gameover::Win(Color::Yellow));
+    }
+
+    #[test]
+    fn test_connect_five_needs_five() {
+        let mut game = GameState::<Dims<9, 6>>::with_rules(Rules {
+            connect: 5,
+            ..Rules::default()
+        });
+        // Yellow builds a horizontal line on the bottom row while Red stacks on top of it
+        for column in [0, 0, 1, 1, 2, 2, 3, 3] {
+            game.make_move(column);
+        }
+        assert_eq!(game.gameover_state(), Gameover::None);
+        game.make_move(4);
+        assert_eq!(game.gameover_state(), Gameover::Win(Color::Yellow));
+    }
+
+    #[test]
+    fn test_connect_four_line_does_not_win_connect_five() {
+        let mut game: GameState = GameState::from_fen(
+            "......./......./......./......./rrr..../yyyy...",
+            Some(Color::Red),
+        )
+        .unwrap();
+        assert_eq!(game.gameover_state(), Gameover::Win(Color::Yellow));
+        game.rules.connect = 5;
+        assert_eq!(game.gameover_state(), Gameover::None);
+    }
+
+    #[test]
+    fn test_tie_on_board_smaller_than_win_threshold() {
+        // Nobody can connect four on a 3x2 board, and the full board must still end the game
+        let mut game = GameState::<Dims<3, 2>>::new();
+        for column in [0, 0, 1, 1, 2] {
+            game.make_move(column);
+        }
+        assert_eq!(game.gameover_state(), Gameover::None);
+        game.make_move(2);
+        assert_eq!(game.gameover_state(), Gameover::Tie);
+    }
+
+    #[test]
+    fn test_misere_line_loses() {
+        let mut game: GameState = GameState::from_fen(
+            "......./......./......./......./rrr..../yyyy...",
+            Some(Color::Red),
+        )
+        .unwrap();
+        game.rules.misere = true;
+        assert_eq!(game.gameover_state(), Gameover::Win(Color::Red));
+    }
+
+    #[test]
+    fn test_cylinder_horizontal_wrap() {
+        let mut game: GameState = GameState::from_fen(
+            "......./......./......./......./rr...rr/yy...yy",
+            Some(Color::Red),
+        )
+        .unwrap();
+        assert_eq!(game.gameover_state(), Gameover::None);
+        game.rules.cylinder = true;
+        assert_eq!(game.gameover_state(), Gameover::Win(Color::Yellow));
+    }
+
+    /// Standard bitboard with the given (column, row) cells set
+    fn cells(cells: &[(u8, u8)]) -> u64 {
+        cells.iter().fold(0, |bits, &(column, row)| {
+            bits | GameState::<Standard>::cell_mask(column, row)
+        })
+    }
+
+    #[test]
+    fn test_winning_cells_cover_whole_line() {
+        let game: GameState =
+            GameState::from_fen("......./......./r....../r....../rr...../yyyyy..", None).unwrap();
+        // All five cells count, not just the first four
+        assert_eq!(
+            game.winning_cells(),
+            cells(&[(0, 0), (1, 0), (2, 0), (3, 0), (4, 0)])
+        );
+        // Brackets mark the winning cells
+        assert_eq!(format!("{:?}", game).matches(']').count(), 5);
+
+        let game: GameState =
+            GameState::from_fen("......./......./r....../r....../rr...../yyyy...", None).unwrap();
+        assert_eq!(
+            game.winning_cells(),
+            cells(&[(0, 0), (1, 0), (2, 0), (3, 0)])
+        );
+        let game: GameState =
+            GameState::from_fen("......./......./r....../r....../ry...../yyy....", None).unwrap();
+        assert_eq!(game.winning_cells(), 0);
+    }
+
+    #[test]
+    fn test_winning_cells_wrap_around_cylinder() {
+        let mut game: GameState = GameState::from_fen(
+            "......./......./.y...../yr...../yr....y/ry.r.yr",
+            Some(Color::Red),
+        )
+        .unwrap();
+        assert_eq!(game.winning_cells(), 0);
+        game.rules.cylinder = true;
+        assert_eq!(
+            game.winning_cells(),
+            cells(&[(5, 0), (6, 1), (0, 2), (1, 3)])
+        );
+    }
+
+    #[test]
+    fn test_classify_finds_line_of_player_to_move() {
+        let mut game: GameState =
+            GameState::from_fen("......./......./......./r....../r....../ryyyy..", None).unwrap();
+        assert_eq!(game.current_player, Color::Red);
+        assert_eq!(game.classify(), Classification::Win(Color::Yellow));
+        // Yellow's line is missed once Yellow is the one to move
+        game.override_current_player(Color::Yellow);
+        assert_eq!(game.gameover_state(), Gameover::None);
+        assert_eq!(game.classify(), Classification::Win(Color::Yellow));
+        game.rules.misere = true;
+        assert_eq!(game.classify(), Classification::Win(Color::Red));
+    }
+
+    #[test]
+    fn test_classify_without_enough_pieces_for_normal_play() {
+        let mut game: GameState = GameState::new();
+        for row in 0..4 {
+            game.red |= GameState::<Standard>::cell_mask(2, row);
+        }
+        assert_eq!(game.gameover_state(), Gameover::None);
+        assert_eq!(game.classify(), Classification::Win(Color::Red));
+        assert_eq!(
+            GameState::<Standard>::new().classify(),
+            Classification::Ongoing
+        );
+    }
+
+    #[test]
+    fn test_classify_both_connected() {
+        let mut game: GameState = GameState::from_fen(
+            "......./......./......./r....../r....../ryyy...",
+            Some(Color::Yellow),
+        )
+        .unwrap();
+        assert_eq!(game.classify(), Classification::Ongoing);
+        // Both players have three in a row, which is a line once connect is lowered
+        game.rules.connect = 3;
+        assert_eq!(game.classify(), Classification::BothConnected);
+        // A pop can complete both lines, in which case the player who popped wins
+        game.rules.popout = true;
+        assert_eq!(game.classify(), Classification::Win(Color::Red));
+    }
+
+    #[test]
+    fn test_classify_tie() {
+        let game: GameState =
+            GameState::from_fen("yrryyry/ryrrryr/rryyyrr/yyyrryy/rryyyry/yyrrryr", None).unwrap();
+        assert_eq!(game.classify(), Classification::Tie);
+    }
+
+    #[test]
+    fn test_cylinder_diagonal_wrap() {
+        // Up-right diagonal from column 6 across the edge to column 2
+        let mut game: GameState = GameState::from_fen(
+            "......./......./.y...../yr...../yr....y/ry.r.yr",
+            Some(Color::Red),
+        )
+        .unwrap();
+        assert_eq!(game.gameover_state(), Gameover::None);
+        game.rules.cylinder = true;
+        assert_eq!(game.gameover_state(), Gameover::Win(Color::Yellow));
+    }
+
+    #[test]
+    fn test_cylinder_keeps_vertical_lines_in_their_column() {
+        let mut game: GameState = GameState::from_fen(
+            "......./......./......./y....../y....../y.rrr.y",
+            Some(Color::Red),
+        )
+        .unwrap();
+        game.rules.cylinder = true;
+        assert_eq!(game.gameover_state(), Gameover::None);
+    }
+
+    #[test]
+    fn test_cylinder_line_cannot_reuse_cells() {
+        // A full row of three is not four in a row, even though it wraps around
+        let mut game =
+            GameState::<Dims<3, 4>>::from_fen(".../.../rr./yyy", Some(Color::Red)).unwrap();
+        game.rules.cylinder = true;
+        assert_eq!(game.gameover_state(), Gameover::None);
+    }
+
+    #[test]
+    fn test_obstacle_acts_as_floor() {
+        let mut game: GameState =
+            GameState::from_fen("......./......./......./#....../......./.......", None).unwrap();
+        assert_eq!(game.get_height(0), 3);
+        assert!(game.make_move(0));
+        assert_eq!(
+            game.board_fen(),
+            "......./......./y....../#....../......./......."
+        );
+        game.undo_move(0);
+        assert_eq!(game.get_height(0), 3);
+    }
+
+    #[test]
+    fn test_obstacle_breaks_line() {
+        let game: GameState = GameState::from_fen(
+            "......./......./......./......./rrr..../yy#yy..",
+            Some(Color::Red),
+        )
+        .unwrap();
+        assert_eq!(game.gameover_state(), Gameover::None);
+    }
+
+    #[test]
+    fn test_tie_with_obstacles() {
+        // Column 1 can never be filled below its obstacle, but the game still ends once it's full above it
+        let game = GameState::<Dims<3, 2>>::from_fen("#yr/.ry", Some(Color::Yellow)).unwrap();
+        assert_eq!(game.gameover_state(), Gameover::Tie);
+        assert_eq!(game.board_fen(), "#yr/.ry");
+    }
+
+    fn popout_from_fen(fen: &str, color: Color) -> GameState {
+        let mut game = GameState::from_fen(fen, Some(color)).unwrap();
+        game.rules.popout = true;
+        game
+    }
+
+    #[test]
+    fn test_pop_and_undo_pop() {
+        let mut game = popout_from_fen(
+            "......./......./......./r....../y....../yr.....",
+            Color::Yellow,
+        );
+        let before = game.clone();
+        assert!(!game.can_pop(1)); // Red's piece
+        assert!(game.play(Move::Pop(0)));
+        assert_eq!(
+            game.board_fen(),
+            "......./......./......./......./r....../yr....."
+        );
+        assert_eq!(game.current_player, Color::Red);
+        game.unplay(Move::Pop(0));
+        assert_eq!(game, before);
+    }
+
+    #[test]
+    fn test_pops_need_popout_rules() {
+        let mut game: GameState = GameState::from_fen(
+            "......./......./......./......./......./y......",
+            Some(Color::Yellow),
+        )
+        .unwrap();
+        assert!(!game.can_pop(0));
+        assert!(!game.play(Move::Pop(0)));
+        game.rules.popout = true;
+        assert!(game.legal_moves().contains(&Move::Pop(0)));
+    }
+
+    #[test]
+    fn test_pop_can_complete_opponent_line() {
+        let mut game = popout_from_fen(
+            "......./......./......./......./ryy..../yrrr...",
+            Color::Yellow,
+        );
+        assert_eq!(game.gameover_state(), Gameover::None);
+        game.play(Move::Pop(0));
+        assert_eq!(game.gameover_state(), Gameover::Win(Color::Red));
+    }
+
+    #[test]
+    fn test_pop_completing_both_lines_wins_for_popper() {
+        let mut game = popout_from_fen(
+            "......./......./......./y....../ryyy.../yrrr...",
+            Color::Yellow,
+        );
+        assert_eq!(game.gameover_state(), Gameover::None);
+        game.play(Move::Pop(0));
+        assert_eq!(game.gameover_state(), Gameover::Win(Color::Yellow));
+    }
+
+    #[test]
+    fn test_full_board_is_not_a_tie_with_popout() {
+        let game = popout_from_fen(
+            "yrryyry/ryrrryr/rryyyrr/yyyrryy/rryyyry/yyrrryr",
+            Color::Yellow,
+        );
+        assert_eq!(game.gameover_state(), Gameover::None);
+        assert!(game
+            .legal_moves()
+            .iter()
+            .all(|mv| matches!(mv, Move::Pop(_))));
+    }
 }
@@ -0,0 +1,306 @@
+//! Exact keys for positions, for book and tablebase files that can't afford hash collisions.
+//!
+//! [`GameState::key`] packs a position into one bit more per column than it has rows, 49 bits on the standard board.
+//! [`PositionIndex`] goes further and numbers every position with a given piece count from zero without gaps,
+//! so a table can be a flat array with one entry per position.
+
+use crate::color::Color;
+use crate::dimensions::{Bitboard, Dimensions};
+use crate::gamestate::GameState;
+use std::marker::PhantomData;
+use std::sync::LazyLock;
+
+/// Pascal's triangle up to the most cells any board can have
+static BINOMIALS: LazyLock<Vec<Vec<u128>>> = LazyLock::new(|| binomials(u128::BITS));
+
+impl<D: Dimensions> GameState<D> {
+    /// A key that is unique to the pieces on the board and the player to move, among positions from games that
+    /// Yellow started.
+    ///
+    /// It is the player to move's pieces, plus the filled cells, plus the bottom row. The sum sets the cell above the top
+    /// piece of each column and keeps the player to move's pieces below it, so every column reads as its height and
+    /// colours. Which colour those pieces are follows from the piece count when Yellow started, so a position and the
+    /// same position with the colours swapped and the other player to move share a key. Obstacles are left out too,
+    /// so positions with obstacles may share keys.
+    pub fn key(&self) -> D::Bits {
+        let full_board = D::Bits::from_u128(D::FULL_BOARD_MASK);
+        let bottom_row = full_board & !(full_board << 1);
+        let current = match self.current_player {
+            Color::Red => self.red,
+            Color::Yellow => self.yellow,
+        };
+        current + self.filled() + bottom_row
+    }
+}
+
+/// Numbers the positions on a `D` board with the same piece count from zero without gaps.
+///
+/// Ranks count every way to colour the stacked pieces, so they include won positions and stacking orders that
+/// no game would produce, in exchange for being quick to compute. Building the index works out how many ways there
+/// are to stack each number of pieces, so it is worth keeping one around rather than building it for every position.
+pub struct PositionIndex<D: Dimensions> {
+    /// `heights[columns][pieces]` is the number of ways to stack `pieces` pieces into `columns` columns
+    heights: Vec<Vec<u128>>,
+    dimensions: PhantomData<D>,
+}
+
+impl<D: Dimensions> Default for PositionIndex<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Dimensions> PositionIndex<D> {
+    pub fn new() -> Self {
+        let cells = D::CELLS as usize;
+        let mut heights = vec![vec![0u128; cells + 1]];
+        heights[0][0] = 1;
+        for columns in 1..=D::WIDTH as usize {
+            let mut ways = vec![0u128; cells + 1];
+            for (filled, &count) in heights[columns - 1].iter().enumerate() {
+                for height in 0..=(D::HEIGHT as usize).min(cells - filled) {
+                    ways[filled + height] += count;
+                }
+            }
+            heights.push(ways);
+        }
+        PositionIndex {
+            heights,
+            dimensions: PhantomData,
+        }
+    }
+
+    /// Number of positions with `pieces` pieces that [`PositionIndex::rank`] numbers.
+    ///
+    /// Panics if the count doesn't fit in a u128, which can happen on the biggest [`WideDims`](crate::dimensions::WideDims) boards.
+    pub fn count(&self, pieces: u32) -> u128 {
+        self.heights[D::WIDTH as usize][pieces as usize]
+            .checked_mul(BINOMIALS[pieces as usize][yellow_pieces(pieces) as usize])
+            .expect("position count to fit in a u128")
+    }
+
+    /// Number `game` among the positions with the same piece count, from 0 up to [`PositionIndex::count`].
+    ///
+    /// Yellow is assumed to have started, as the player to move follows from the piece counts.
+    /// Returns `None` for positions that couldn't be made that way, with obstacles, floating pieces or the wrong piece counts,
+    /// and for ranks too big for a u128.
+    /// Won positions are numbered like any other.
+    pub fn rank(&self, game: &GameState<D>) -> Option<u128> {
+        let full_board = D::Bits::from_u128(D::FULL_BOARD_MASK);
+        let filled = game.filled();
+        let pieces = filled.count_ones();
+        if game.obstacles != D::Bits::ZERO
+            || game.yellow & game.red != D::Bits::ZERO
+            || filled & !full_board != D::Bits::ZERO
+            || game.floating_piece().is_some()
+            || game.yellow.count_ones() != yellow_pieces(pieces)
+        {
+            return None;
+        }
+
+        // Number the column heights first, counting how many ways there are to fill the columns with fewer pieces in
+        // the current column, then the columns to its right with what remains
+        let mut heights_rank = 0;
+        let mut remaining = pieces;
+        for column in 0..D::WIDTH {
+            let column_mask =
+                D::Bits::from_u128(D::COLUMN_MASK << (column as u32 * D::STRIDE as u32));
+            let height = (filled & column_mask).count_ones();
+            let columns_right = &self.heights[(D::WIDTH - column - 1) as usize];
+            for lower in 0..height {
+                heights_rank += columns_right[(remaining - lower) as usize];
+            }
+            remaining -= height;
+        }
+
+        // Then which of the filled cells are Yellow's, going up each column from left to right,
+        // as a combination in the combinatorial number system
+        let mut colours_rank = 0;
+        let mut yellow_seen = 0;
+        let mut unseen = filled;
+        let mut index = 0;
+        while unseen != D::Bits::ZERO {
+            let cell = D::Bits::ONE << unseen.trailing_zeros();
+            if game.yellow & cell != D::Bits::ZERO {
+                yellow_seen += 1;
+                colours_rank += BINOMIALS[index][yellow_seen];
+            }
+            unseen ^= cell;
+            index += 1;
+        }
+
+        let colourings = BINOMIALS[pieces as usize][yellow_pieces(pieces) as usize];
+        heights_rank
+            .checked_mul(colourings)
+            .and_then(|rank| rank.checked_add(colours_rank))
+    }
+
+    /// The position with `pieces` pieces that [`PositionIndex::rank`] numbers as `rank`, with Yellow having started.
+    ///
+    /// Returns `None` if `rank` isn't below [`PositionIndex::count`].
+    pub fn unrank(&self, pieces: u32, rank: u128) -> Option<GameState<D>> {
+        if pieces > D::CELLS || rank >= self.count(pieces) {
+            return None;
+        }
+        let yellow_count = yellow_pieces(pieces);
+        let colourings = BINOMIALS[pieces as usize][yellow_count as usize];
+        let mut heights_rank = rank / colourings;
+        let mut colours_rank = rank % colourings;
+
+        // Undo the numbering of the column heights, trying the lowest height for each column first
+        let mut game = GameState::new();
+        let mut filled = D::Bits::ZERO;
+        let mut remaining = pieces;
+        for column in 0..D::WIDTH {
+            let columns_right = &self.heights[(D::WIDTH - column - 1) as usize];
+            let mut height = 0;
+            while heights_rank >= columns_right[(remaining - height) as usize] {
+                heights_rank -= columns_right[(remaining - height) as usize];
+                height += 1;
+            }
+            for row in 0..height {
+                filled |= GameState::<D>::cell_mask(column, row as u8);
+            }
+            remaining -= height;
+        }
+
+        // Then the combination of Yellow's cells, taking the highest one first
+        let mut yellow_indices = Vec::with_capacity(yellow_count as usize);
+        let mut below = pieces as usize;
+        for seen in (1..=yellow_count as usize).rev() {
+            let index = (seen - 1..below)
+                .rev()
+                .find(|&index| BINOMIALS[index][seen] <= colours_rank)
+                .expect("every colouring rank to decode");
+            colours_rank -= BINOMIALS[index][seen];
+            yellow_indices.push(index);
+            below = index;
+        }
+
+        let mut unseen = filled;
+        let mut index = 0;
+        while unseen != D::Bits::ZERO {
+            let cell = D::Bits::ONE << unseen.trailing_zeros();
+            if yellow_indices.contains(&index) {
+                game.yellow |= cell;
+            } else {
+                game.red |= cell;
+            }
+            unseen ^= cell;
+            index += 1;
+        }
+        if !pieces.is_multiple_of(2) {
+            game.current_player = Color::Red;
+        }
+        game.ply = pieces;
+        Some(game)
+    }
+}
+
+/// Yellow moves first, so they have the extra piece after an odd number of moves
+fn yellow_pieces(pieces: u32) -> u32 {
+    pieces.div_ceil(2)
+}
+
+/// Pascal's triangle up to `n`, with `binomials[n][k]` being n choose k
+fn binomials(n: u32) -> Vec<Vec<u128>> {
+    let mut rows: Vec<Vec<u128>> = Vec::with_capacity(n as usize + 1);
+    for row in 0..=n as usize {
+        let mut values = vec![1; row + 1];
+        for k in 1..row {
+            values[k] = rows[row - 1][k - 1] + rows[row - 1][k];
+        }
+        rows.push(values);
+    }
+    // Leave room to ask for more than n items, which is always 0
+    for row in rows.iter_mut() {
+        row.resize(n as usize + 2, 0);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dimensions::{Dims, Standard};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_key_fits_in_49_bits() {
+        let game: GameState = GameState::from_moves("4453342").unwrap();
+        assert!(game.key() < 1 << 49);
+        assert_eq!(GameState::<Standard>::new().key(), 0x0408_1020_4081);
+    }
+
+    #[test]
+    fn test_keys_and_ranks_are_unique() {
+        // Every way to put yellow, red or nothing in each cell of a 3x3 board, keeping the ones that can be ranked
+        type Small = Dims<3, 3>;
+        let index = PositionIndex::<Small>::new();
+        let mut positions = Vec::new();
+        for mut code in 0..3u32.pow(9) {
+            let mut game = GameState::<Small>::new();
+            for column in 0..3 {
+                for row in 0..3 {
+                    match code % 3 {
+                        1 => game.yellow |= GameState::<Small>::cell_mask(column, row),
+                        2 => game.red |= GameState::<Small>::cell_mask(column, row),
+                        _ => {}
+                    }
+                    code /= 3;
+                }
+            }
+            if game.red.count_ones() < game.yellow.count_ones() {
+                game.current_player = Color::Red;
+            }
+            if let Some(rank) = index.rank(&game) {
+                positions.push((game, rank));
+            }
+        }
+
+        let keys: HashSet<_> = positions.iter().map(|(game, _)| game.key()).collect();
+        assert_eq!(keys.len(), positions.len());
+        for pieces in 0..=9 {
+            let ranks: HashSet<_> = positions
+                .iter()
+                .filter(|(game, _)| game.filled().count_ones() == pieces)
+                .map(|&(_, rank)| rank)
+                .collect();
+            let count = index.count(pieces);
+            assert_eq!(ranks.len() as u128, count, "{} pieces", pieces);
+            assert!(ranks.iter().all(|&rank| rank < count));
+            assert_eq!(index.unrank(pieces, count), None);
+        }
+        for (game, rank) in positions.iter() {
+            let unranked = index.unrank(game.filled().count_ones(), *rank).unwrap();
+            assert_eq!(&unranked, game);
+        }
+    }
+
+    #[test]
+    fn test_key_assumes_yellow_started() {
+        // A red piece with Yellow to move only comes up if Red started
+        let red_started: GameState = GameState::from_fen(
+            "......./......./......./......./......./...r... y r 1",
+            None,
+        )
+        .unwrap();
+        let yellow_started: GameState = GameState::from_moves("4").unwrap();
+        assert_eq!(red_started.key(), yellow_started.key());
+    }
+
+    #[test]
+    fn test_unrankable_positions() {
+        let index = PositionIndex::<Standard>::new();
+        // Red started
+        let game: GameState = GameState::from_fen(
+            "......./......./......./......./......./...r... y r 1",
+            None,
+        )
+        .unwrap();
+        assert_eq!(index.rank(&game), None);
+        let game: GameState =
+            GameState::from_fen("......./......./......./......./......./...#...", None).unwrap();
+        assert_eq!(index.rank(&game), None);
+    }
+}
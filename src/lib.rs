@@ -0,0 +1,53 @@
+//! Connect Four engines and game logic, shared by the terminal game and our own tools.
+//!
+//! Everything meant for outside use is re-exported here, from positions and games to the engines,
+//! the Power Up and three-player variants, game records and tablebases. The modules themselves are private,
+//! so their helpers can change without breaking anyone.
+
+mod color;
+mod dimensions;
+mod engine;
+mod game;
+mod gamestate;
+mod indexing;
+mod mcts;
+mod notation;
+mod player;
+mod pns;
+mod powerup;
+mod reachability;
+mod record;
+mod rules;
+#[cfg(feature = "serde")]
+mod serialization;
+mod tablebase;
+mod threeplayer;
+mod transposition;
+
+pub use color::{Color, Gameover};
+pub use dimensions::{Bitboard, Dimensions, Dims, Standard, WideDims};
+pub use engine::{choose_move, evaluate_moves, negamax_entrypoint, Difficulty, EngineConfig};
+pub use game::{Game, HistoryError};
+pub use gamestate::{Classification, FenError, GameState, Move, Moves};
+pub use indexing::PositionIndex;
+pub use mcts::MctsConfig;
+pub use notation::MoveSequenceError;
+pub use player::{
+    Analysis, GreedyPlayer, Interrupt, MctsPlayer, NegamaxPlayer, Player, RandomPlayer, TurnBased,
+};
+pub use pns::{solve, ProofResult, Verdict};
+pub use powerup::{
+    evaluate_power_moves, power_up_search, Inventory, PowerMove, PowerUpGame, PowerUpPlayer,
+    Special, Undo,
+};
+pub use reachability::Unreachable;
+pub use record::{GameRecord, RecordError, RecordedMove};
+pub use rules::Rules;
+pub use tablebase::Tablebase;
+pub use threeplayer::{
+    evaluate_columns, paranoid_search, ParanoidPlayer, ThreePlayerGame, TriColor, TriGameover,
+};
+pub use transposition::{check_for_table_clear, new_table, Table};
+
+/// The random number generator the engines take, so callers don't need to depend on `rand_mt` themselves
+pub use rand_mt::Mt64;
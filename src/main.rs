@@ -1,77 +1,284 @@
-pub mod color;
-mod engine;
-mod gamestate;
-mod transposition;
-
-use core::panic;
+use std::fmt;
 use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::Duration;
 
-use color::{Color, Gameover};
-use gamestate::GameState;
+use connect_four_bot::{
+    new_table, solve, Analysis, Classification, Color, Difficulty, Dimensions, Dims, EngineConfig,
+    Game, GameRecord, GameState, Gameover, GreedyPlayer, Interrupt, MctsConfig, MctsPlayer, Move,
+    Mt64, NegamaxPlayer, ParanoidPlayer, Player, PowerMove, PowerUpGame, PowerUpPlayer,
+    RandomPlayer, Rules, Special, Standard, Table, ThreePlayerGame, TriColor, TriGameover,
+    TurnBased, WideDims,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Gamemode {
-    PlayerVsPlayer,
-    PlayerVsComputer,
-    ComputerVsComputer,
+enum PlayerKind {
+    Human,
+    Computer,
+    Mcts,
+    Random,
+    Greedy,
 }
 
-fn main() {
-    run_game();
+impl PlayerKind {
+    /// Every kind of player for the standard game
+    const ALL: [PlayerKind; 5] = [
+        PlayerKind::Human,
+        PlayerKind::Computer,
+        PlayerKind::Mcts,
+        PlayerKind::Random,
+        PlayerKind::Greedy,
+    ];
+
+    /// Kinds of player for Power Up and three-player games, which the Monte Carlo search can't play
+    const VARIANTS: [PlayerKind; 4] = [
+        PlayerKind::Human,
+        PlayerKind::Computer,
+        PlayerKind::Random,
+        PlayerKind::Greedy,
+    ];
+
+    const fn description(&self) -> &'static str {
+        match self {
+            PlayerKind::Human => "Human",
+            PlayerKind::Computer => "Computer",
+            PlayerKind::Mcts => "Monte Carlo computer",
+            PlayerKind::Random => "Random mover",
+            PlayerKind::Greedy => "Greedy mover",
+        }
+    }
 }
 
-fn run_game() -> Option<()> {
+/// Maximum number of nodes the proof-number search may create for each question it answers
+const PROOF_NODE_LIMIT: usize = 5_000_000;
+
+/// Deepest the three-player search goes, since paranoid search prunes far less than a two-player search
+const THREE_PLAYER_MAX_DEPTH: u16 = 8;
+
+/// Deepest the Power Up search goes, since special pieces make the game tree far too wide to solve
+const POWER_UP_MAX_DEPTH: u16 = 6;
+
+/// Creates players on demand, including partway through a game when a human swaps sides.
+struct PlayerFactory<D: Dimensions> {
+    table: Arc<Table<D>>,
+    engine_config: EngineConfig,
+    mcts_config: MctsConfig,
+    rng: Mt64,
+}
+
+impl<D: Dimensions> PlayerFactory<D> {
+    fn create(&mut self, kind: PlayerKind) -> Box<dyn Player<GameState<D>>> {
+        // Every player gets its own generator, derived from the game's seed so the game can be replayed
+        let rng = Mt64::new(self.rng.next_u64());
+        match kind {
+            PlayerKind::Human => Box::new(HumanPlayer),
+            PlayerKind::Computer => Box::new(NegamaxPlayer::new(
+                self.table.clone(),
+                self.engine_config,
+                rng,
+            )),
+            PlayerKind::Mcts => Box::new(MctsPlayer::new(self.mcts_config, rng)),
+            PlayerKind::Random => Box::new(RandomPlayer::new(rng)),
+            PlayerKind::Greedy => Box::new(GreedyPlayer::new(rng)),
+        }
+    }
+}
+
+/// Index of the player for `color` in the game loop's list of players
+const fn seat(color: Color) -> usize {
+    match color {
+        Color::Yellow => 0,
+        Color::Red => 1,
+    }
+}
+
+fn main() {
     println!("\n==========CONNECT FOUR==========");
     println!("Enter 'q' at any time to quit the game.");
 
-    let mut transposition_table = transposition::new_table();
-    let mut board = load_game()?;
-    let (mut gamemode, mut player_color) = determine_gamemode()?;
-    override_starting_color(&mut board)?;
+    println!("How many players? (2/3)");
+    let three_players = match read_input().as_deref() {
+        Some("3") => true,
+        Some(_) => false,
+        None => return,
+    };
+
+    match (three_players, determine_board_size()) {
+        (false, Some((7, 6))) => run_game::<Standard>(),
+        (false, Some((8, 7))) => run_game::<Dims<8, 7>>(),
+        (false, Some((9, 7))) => run_game::<WideDims<9, 7>>(),
+        (false, Some((6, 5))) => run_game::<Dims<6, 5>>(),
+        (true, Some((7, 6))) => run_three_player_game::<Standard>(),
+        (true, Some((8, 7))) => run_three_player_game::<Dims<8, 7>>(),
+        (true, Some((9, 7))) => run_three_player_game::<WideDims<9, 7>>(),
+        (true, Some((6, 5))) => run_three_player_game::<Dims<6, 5>>(),
+        (_, Some(size)) => unreachable!("Unsupported board size {:?}", size),
+        (_, None) => None,
+    };
+}
+
+fn run_game<D: Dimensions>() -> Option<()> {
+    let rules = determine_rules()?;
+    let board = load_game::<D>(rules)?;
+    println!("Would you like to play Power Up, with anvil, bomb, wall and x2 pieces? (y/n)");
+    let power_up = read_input()? == "y";
+    let kinds: &[PlayerKind] = if power_up {
+        &PlayerKind::VARIANTS
+    } else {
+        &PlayerKind::ALL
+    };
+    let (yellow, red) = determine_gamemode(kinds)?;
+    let engine_config = if [yellow, red]
+        .iter()
+        .any(|&kind| kind == PlayerKind::Human || kind == PlayerKind::Computer)
+    {
+        determine_difficulty()?
+    } else {
+        EngineConfig::default()
+    };
+    let mcts_config = if [yellow, red].contains(&PlayerKind::Mcts) {
+        determine_mcts_budget()?
+    } else {
+        MctsConfig::default()
+    };
+    let seed = determine_seed()?;
+    println!("Using random seed {}", seed);
+
+    // Loaded positions skip the checks that normal play relies on
+    match board.classify() {
+        Classification::Ongoing => {}
+        Classification::Win(color) => {
+            println!("{:?}", board);
+            println!("This position is already over, {} has won!", color);
+            return None;
+        }
+        Classification::Tie => {
+            println!("{:?}", board);
+            println!("This position is already over, it's a tie!");
+            return None;
+        }
+        Classification::BothConnected => {
+            println!("{:?}", board);
+            println!(
+                "Both players have a line under these rules, so this position can't be played."
+            );
+            return None;
+        }
+    }
+
+    if power_up {
+        let mut kinds = [PlayerKind::Human; 2];
+        kinds[seat(Color::Yellow)] = yellow;
+        kinds[seat(Color::Red)] = red;
+        return run_power_up_game(board, kinds, engine_config, seed);
+    }
+
+    let mut factory = PlayerFactory {
+        table: Arc::new(new_table()),
+        engine_config,
+        mcts_config,
+        rng: Mt64::new(seed),
+    };
+    let mut players = [factory.create(yellow), factory.create(red)];
 
-    println!("\nStarting game in {:?} mode", gamemode);
+    println!(
+        "\nStarting game with {} as {} and {} as {}",
+        players[seat(Color::Yellow)].name(),
+        Color::Yellow,
+        players[seat(Color::Red)].name(),
+        Color::Red
+    );
     println!();
 
+    let mut game = Game::new(board);
+    // Evals the players gave for each move in `game`, for the game record
+    let mut evals: Vec<Option<i32>> = Vec::new();
+
     loop {
-        transposition::check_for_table_clear(&transposition_table);
+        let board = game.board();
         println!("=====\n{:?}", board);
-        match gamemode {
-            Gamemode::PlayerVsPlayer => {
-                let ctrl = make_human_turn(&mut board);
-                if let ControlFlow::Break(_) = ctrl {
-                    break;
-                }
-                if let ControlFlow::Continue(true) = ctrl {
-                    // Switch to computer mode
-                    player_color = board.current_player.opposite();
-                    gamemode = Gamemode::PlayerVsComputer;
-                    println!(
-                        "Switching to Human vs Computer mode. Computer will play as {}.",
-                        player_color.opposite()
+        let current = seat(board.current_player);
+        println!(
+            "Move {}: {} {}'s turn",
+            game.ply() + 1,
+            board.current_player,
+            players[current].name()
+        );
+        match players[current].choose_move_after(&game.history(), board) {
+            ControlFlow::Continue(mv) => {
+                if let Err(error) = game.play(mv) {
+                    panic!(
+                        "{} tried to play an illegal move: {}",
+                        players[current].name(),
+                        error
                     );
                 }
+                evals.truncate(game.ply() - 1);
+                evals.push(players[current].last_eval());
+                if let Some(analysis) = players[current].last_analysis() {
+                    print_analysis(analysis, mv);
+                }
+                println!("{} plays {}", game.board().current_player.opposite(), mv);
             }
-            Gamemode::PlayerVsComputer => {
-                if board.current_player == player_color {
-                    let ctrl = make_human_turn(&mut board);
-                    if let ControlFlow::Break(_) = ctrl {
-                        break;
+            ControlFlow::Break(Interrupt::Quit) => break,
+            ControlFlow::Break(Interrupt::SwapSides) => {
+                swap_sides(&mut players, current, || {
+                    factory.create(PlayerKind::Computer)
+                });
+                println!(
+                    "{} will now be played by {} and {} by {}.",
+                    Color::Yellow,
+                    players[seat(Color::Yellow)].name(),
+                    Color::Red,
+                    players[seat(Color::Red)].name()
+                );
+                continue;
+            }
+            ControlFlow::Break(Interrupt::Undo) => {
+                // Take back the computer's moves too, until a person is to move again.
+                // Against another person that is just the last move.
+                loop {
+                    match game.undo() {
+                        Ok(mv) => println!("Took back {}", mv),
+                        Err(error) => {
+                            println!("Can't undo: {}", error);
+                            break;
+                        }
                     }
-                    if let ControlFlow::Continue(true) = ctrl {
-                        // Yield to computer
-                        player_color = board.current_player.opposite();
-                        println!("Switching player color to {}.", player_color);
+                    if players[seat(game.board().current_player)].is_human() {
+                        break;
                     }
-                } else {
-                    make_computer_turn(&mut board, &mut transposition_table);
                 }
+                continue;
+            }
+            ControlFlow::Break(Interrupt::Redo) => {
+                match game.redo() {
+                    Ok(mv) => println!("Replayed {}", mv),
+                    Err(error) => println!("Can't redo: {}", error),
+                }
+                continue;
+            }
+            ControlFlow::Break(Interrupt::Save) => {
+                save_record(&game, &evals, &players, &engine_config, seed)?;
+                continue;
             }
-            Gamemode::ComputerVsComputer => {
-                make_computer_turn(&mut board, &mut transposition_table);
+            ControlFlow::Break(Interrupt::Load) => {
+                // Fall through to the checks below, in case the loaded game is already over
+                let rules = game.board().rules;
+                let record = load_record(&mut game)?;
+                evals = record.moves.iter().map(|recorded| recorded.eval).collect();
+                if game.board().rules != rules {
+                    // Nothing found under the old rules will come up again
+                    factory
+                        .table
+                        .write()
+                        .expect("rw lock on tt to not be poisoned")
+                        .clear();
+                }
             }
         }
 
-        match board.gameover_state() {
+        match game.board().gameover_state() {
             Gameover::Win(color) => {
                 println!("\nGame Over! {} wins!", color);
                 break;
@@ -82,33 +289,575 @@ fn run_game() -> Option<()> {
             }
             Gameover::None => {}
         }
+
+        if game.board().rules.popout {
+            let repetitions = game.repetitions();
+            if repetitions >= Rules::REPETITIONS_FOR_DRAW {
+                println!(
+                    "\nGame Over! The same position came up {} times, it's a tie!",
+                    repetitions
+                );
+                break;
+            }
+        }
     }
 
     println!("Final board state:");
-    println!("{:?}", board);
-    return None;
+    println!("{:?}", game.board());
+    let moves: Vec<String> = game.moves().iter().map(Move::to_string).collect();
+    println!("Moves played: {}", moves.join(", "));
+    match game.board().to_moves() {
+        Some(moves) => println!(
+            "Move sequence from the empty board, starting with {}: {}",
+            game.board().starting_player(),
+            moves
+        ),
+        None => println!("This game can't be written as a move sequence from the empty board"),
+    }
+    println!("Random seed for this game was {}", seed);
+    println!("Would you like to save this game? (y/n)");
+    if read_input()? == "y" {
+        save_record(&game, &evals, &players, &engine_config, seed)?;
+    }
+    None
 }
 
-fn load_game() -> Option<GameState> {
-    println!("Would you like to load a game from FEN? (y/n)");
-    let input = read_input()?;
+/// Hand the seat `current` over to the computer, and give the person there the next seat in turn order that a
+/// computer plays. With no computer to swap with, `computer` joins the game instead.
+fn swap_sides<G: TurnBased>(
+    players: &mut [Box<dyn Player<G>>],
+    current: usize,
+    computer: impl FnOnce() -> Box<dyn Player<G>>,
+) {
+    let seats = players.len();
+    match (1..seats)
+        .map(|offset| (current + offset) % seats)
+        .find(|&other| !players[other].is_human())
+    {
+        Some(other) => players.swap(current, other),
+        None => players[current] = computer(),
+    }
+}
 
-    if input == "y" {
-        println!("Enter FEN string:");
+/// Show what an engine found out before playing `chosen`.
+fn print_analysis<M: Copy + PartialEq + fmt::Display>(analysis: &Analysis<M>, chosen: M) {
+    match analysis {
+        Analysis::Evals {
+            evals,
+            cleared_entries,
+        } => {
+            if let Some(entries) = cleared_entries {
+                println!(
+                    "Transposition table reached {} entries, cleared it",
+                    entries
+                );
+            }
+            println!();
+            for (mv, eval) in evals.iter() {
+                println!("Playing {} evaluated to {}", mv, eval);
+            }
+            let best_eval = evals.first().map(|&(_, eval)| eval);
+            if let Some(&(_, eval)) = evals.iter().find(|&&(mv, _)| mv == chosen) {
+                if Some(eval) < best_eval {
+                    println!("Deliberately playing {} instead", chosen);
+                }
+                println!("Playing {} has an eval of {}", chosen, eval);
+            }
+        }
+        Analysis::WinRate(win_rate) => println!(
+            "Playing {} has an estimated win rate of {:.1}%",
+            chosen,
+            win_rate * 100.0
+        ),
+    }
+}
+
+/// Ask for a file name and write `game` to it as a game record.
+fn save_record<D: Dimensions>(
+    game: &Game<D>,
+    evals: &[Option<i32>],
+    players: &[Box<dyn Player<GameState<D>>>; 2],
+    engine_config: &EngineConfig,
+    seed: u64,
+) -> Option<()> {
+    let mut record = GameRecord::new(game);
+    for (recorded, &eval) in record.moves.iter_mut().zip(evals) {
+        recorded.eval = eval;
+    }
+    record.set_header(
+        GameRecord::YELLOW_HEADER,
+        players[seat(Color::Yellow)].name(),
+    );
+    record.set_header(GameRecord::RED_HEADER, players[seat(Color::Red)].name());
+    record.set_header(GameRecord::ENGINE_HEADER, &engine_config.to_string());
+    record.set_header(GameRecord::SEED_HEADER, &seed.to_string());
+
+    println!("Enter a file name to save the game to:");
+    let path = read_path()?;
+    match std::fs::write(&path, record.to_string()) {
+        Ok(()) => println!("Saved the game to {}", path),
+        Err(error) => println!("Couldn't save the game: {}", error),
+    }
+    Some(())
+}
+
+/// Ask for a game record file and replace `game` with the game in it.
+/// Records without a rules header are played under the current rules.
+/// Returns the record, so the caller can pick up the evals stored in it.
+fn load_record<D: Dimensions>(game: &mut Game<D>) -> Option<GameRecord> {
+    println!("Enter the file name of the game to load:");
+    loop {
+        let path = read_path()?;
+        let loaded = std::fs::read_to_string(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|text| GameRecord::parse(&text).map_err(|error| error.to_string()))
+            .and_then(|record| {
+                let replayed = record
+                    .replay(game.board().rules)
+                    .map_err(|error| error.to_string())?;
+                Ok((record, replayed))
+            });
+        match loaded {
+            Ok((record, replayed)) => {
+                *game = replayed;
+                println!("Loaded {} moves from {}", game.ply(), path);
+                match record.result() {
+                    Some(Gameover::Win(color)) => println!("The record says {} won.", color),
+                    Some(Gameover::Tie) => println!("The record says the game was a tie."),
+                    _ => {}
+                }
+                return Some(record);
+            }
+            Err(error) => println!("Couldn't load the game, {}. Please try again:", error),
+        }
+    }
+}
+
+/// Game loop for the Power Up variant.
+/// The usual engines don't know about special pieces, so the computer uses the Power Up search.
+fn run_power_up_game<D: Dimensions>(
+    board: GameState<D>,
+    kinds: [PlayerKind; 2],
+    engine_config: EngineConfig,
+    seed: u64,
+) -> Option<()> {
+    let mut game = PowerUpGame::new(board);
+    let depth = engine_config
+        .max_depth
+        .map_or(POWER_UP_MAX_DEPTH, |depth| depth.min(POWER_UP_MAX_DEPTH));
+    let mut rng = Mt64::new(seed);
+    let mut players = kinds.map(|kind| power_up_player::<D>(kind, depth, &mut rng));
+
+    loop {
+        println!("=====\n{:?}", game);
+        let mover = game.current_player();
+        let current = seat(mover);
+        println!("{} {}'s turn", mover, players[current].name());
+        match players[current].choose_move(&game) {
+            ControlFlow::Continue(mv) => {
+                if game.play(mv).is_none() {
+                    panic!(
+                        "{} tried to play an illegal move: {}",
+                        players[current].name(),
+                        mv
+                    );
+                }
+                if let Some(analysis) = players[current].last_analysis() {
+                    print_analysis(analysis, mv);
+                }
+                println!("{} plays {}", mover, mv);
+            }
+            ControlFlow::Break(Interrupt::Quit) => break,
+            ControlFlow::Break(Interrupt::SwapSides) => {
+                swap_sides(&mut players, current, || {
+                    power_up_player(PlayerKind::Computer, depth, &mut rng)
+                });
+                println!(
+                    "{} will now be played by {} and {} by {}.",
+                    Color::Yellow,
+                    players[seat(Color::Yellow)].name(),
+                    Color::Red,
+                    players[seat(Color::Red)].name()
+                );
+                continue;
+            }
+            ControlFlow::Break(interrupt) => {
+                println!("{:?} isn't available in Power Up games.", interrupt);
+                continue;
+            }
+        }
+
+        match game.gameover_state() {
+            Gameover::Win(color) => {
+                println!("\nGame Over! {} wins!", color);
+                break;
+            }
+            Gameover::Tie => {
+                println!("\nGame Over! It's a tie!");
+                break;
+            }
+            Gameover::None => {}
+        }
+    }
+
+    println!("Final board state:");
+    println!("{:?}", game);
+    println!("Random seed for this game was {}", seed);
+    None
+}
+
+/// A player of `kind` for Power Up games, searching `depth` plies if it is the computer.
+fn power_up_player<D: Dimensions>(
+    kind: PlayerKind,
+    depth: u16,
+    rng: &mut Mt64,
+) -> Box<dyn Player<PowerUpGame<D>>> {
+    // Every player gets its own generator, derived from the game's seed so the game can be replayed
+    let rng = Mt64::new(rng.next_u64());
+    match kind {
+        PlayerKind::Human => Box::new(HumanPlayer),
+        PlayerKind::Computer => Box::new(PowerUpPlayer::new(depth)),
+        PlayerKind::Random => Box::new(RandomPlayer::new(rng)),
+        PlayerKind::Greedy => Box::new(GreedyPlayer::new(rng)),
+        PlayerKind::Mcts => unreachable!("The Monte Carlo search can't play Power Up"),
+    }
+}
+
+impl<D: Dimensions> Player<PowerUpGame<D>> for HumanPlayer {
+    fn name(&self) -> &str {
+        "Human"
+    }
+
+    fn is_human(&self) -> bool {
+        true
+    }
+
+    fn choose_move(&mut self, game: &PowerUpGame<D>) -> ControlFlow<Interrupt, PowerMove> {
+        println!(
+            "Enter column number (1-{}), or a letter followed by a column number to play a special piece: 'a' anvil, 'b' bomb, 'w' wall, 'x' x2{}, or 'q' to quit, 's' to swap sides with the computer:",
+            D::WIDTH,
+            if game.board.rules.popout { ", 'p' pop" } else { "" }
+        );
+        loop {
+            let input = match read_input() {
+                Some(input) => input,
+                None => return ControlFlow::Break(Interrupt::Quit),
+            };
+            if input == "s" {
+                return ControlFlow::Break(Interrupt::SwapSides);
+            }
+            let (kind, column) = match input.chars().next() {
+                Some(letter) if letter.is_ascii_alphabetic() => (Some(letter), input[1..].trim()),
+                _ => (None, input.as_str()),
+            };
+
+            // User inputs 1-indexed column
+            let column = match column.parse::<u8>() {
+                Ok(column) if column <= D::WIDTH && column > 0 => column - 1,
+                _ => {
+                    println!("Please enter a valid column number (1-{})", D::WIDTH);
+                    continue;
+                }
+            };
+            let mv = match kind {
+                None => PowerMove::Normal(Move::Drop(column)),
+                Some('p') => PowerMove::Normal(Move::Pop(column)),
+                Some('a') => PowerMove::Special(Special::Anvil, column),
+                Some('b') => PowerMove::Special(Special::Bomb, column),
+                Some('w') => PowerMove::Special(Special::Wall, column),
+                Some('x') => PowerMove::Special(Special::Double, column),
+                Some(letter) => {
+                    println!("Unknown piece '{}'", letter);
+                    continue;
+                }
+            };
+            if game.is_legal(mv) {
+                return ControlFlow::Continue(mv);
+            }
+            println!("You can't play {} right now!", mv);
+        }
+    }
+}
+
+/// Game loop for three players, who use the paranoid search when the computer plays them.
+fn run_three_player_game<D: Dimensions>() -> Option<()> {
+    let rules = determine_three_player_rules()?;
+    let mut kinds = [PlayerKind::Human; 3];
+    for color in TriColor::ALL {
+        kinds[color.index()] = determine_player_kind(color, &PlayerKind::VARIANTS)?;
+    }
+    // Asked even when only humans play, since a human may swap seats with the computer
+    let depth = if kinds.contains(&PlayerKind::Human) || kinds.contains(&PlayerKind::Computer) {
+        determine_difficulty()?
+            .max_depth
+            .map_or(THREE_PLAYER_MAX_DEPTH, |depth| {
+                depth.min(THREE_PLAYER_MAX_DEPTH)
+            })
+    } else {
+        THREE_PLAYER_MAX_DEPTH
+    };
+    let seed = determine_seed()?;
+    println!("Using random seed {}", seed);
+    let mut rng = Mt64::new(seed);
+    let mut players = kinds.map(|kind| three_player::<D>(kind, depth, &mut rng));
+
+    let mut game = ThreePlayerGame::<D>::with_rules(rules);
+    loop {
+        println!("=====\n{:?}", game);
+        let mover = game.current_player;
+        let current = mover.index();
+        println!("{} {}'s turn", mover, players[current].name());
+        match players[current].choose_move(&game) {
+            ControlFlow::Continue(mv) => {
+                if !game.make_move(mv.column()) {
+                    panic!(
+                        "{} tried to play in a full column: {}",
+                        players[current].name(),
+                        mv
+                    );
+                }
+                if let Some(analysis) = players[current].last_analysis() {
+                    print_analysis(analysis, mv);
+                }
+                println!("{} plays {}", mover, mv);
+            }
+            ControlFlow::Break(Interrupt::Quit) => break,
+            ControlFlow::Break(Interrupt::SwapSides) => {
+                swap_sides(&mut players, current, || {
+                    three_player(PlayerKind::Computer, depth, &mut rng)
+                });
+                for color in TriColor::ALL {
+                    println!(
+                        "{} will now be played by {}.",
+                        color,
+                        players[color.index()].name()
+                    );
+                }
+                continue;
+            }
+            ControlFlow::Break(interrupt) => {
+                println!("{:?} isn't available in three-player games.", interrupt);
+                continue;
+            }
+        }
+
+        match game.gameover_state() {
+            TriGameover::Win(color) => {
+                println!("\nGame Over! {} wins!", color);
+                break;
+            }
+            TriGameover::Tie => {
+                println!("\nGame Over! It's a tie!");
+                break;
+            }
+            TriGameover::None => {}
+        }
+    }
+
+    println!("Final board state:");
+    println!("{:?}", game);
+    println!("Random seed for this game was {}", seed);
+    None
+}
+
+/// A player of `kind` for three-player games, searching `depth` plies if it is the computer.
+fn three_player<D: Dimensions>(
+    kind: PlayerKind,
+    depth: u16,
+    rng: &mut Mt64,
+) -> Box<dyn Player<ThreePlayerGame<D>>> {
+    // Every player gets its own generator, derived from the game's seed so the game can be replayed
+    let rng = Mt64::new(rng.next_u64());
+    match kind {
+        PlayerKind::Human => Box::new(HumanPlayer),
+        PlayerKind::Computer => Box::new(ParanoidPlayer::new(depth)),
+        PlayerKind::Random => Box::new(RandomPlayer::new(rng)),
+        PlayerKind::Greedy => Box::new(GreedyPlayer::new(rng)),
+        PlayerKind::Mcts => unreachable!("The Monte Carlo search can't play three-player games"),
+    }
+}
+
+impl<D: Dimensions> Player<ThreePlayerGame<D>> for HumanPlayer {
+    fn name(&self) -> &str {
+        "Human"
+    }
+
+    fn is_human(&self) -> bool {
+        true
+    }
+
+    fn choose_move(&mut self, game: &ThreePlayerGame<D>) -> ControlFlow<Interrupt, Move> {
+        println!(
+            "Enter column number (1-{}) or 'q' to quit, 's' to swap seats with the computer:",
+            D::WIDTH
+        );
+        loop {
+            let input = match read_input() {
+                Some(input) => input,
+                None => return ControlFlow::Break(Interrupt::Quit),
+            };
+            if input == "s" {
+                return ControlFlow::Break(Interrupt::SwapSides);
+            }
+            // User inputs 1-indexed column
+            match input.parse::<u8>() {
+                Ok(column) if column <= D::WIDTH && column > 0 => {
+                    if game.can_play(column - 1) {
+                        return ControlFlow::Continue(Move::Drop(column - 1));
+                    }
+                    println!("Column {} is full!", column);
+                }
+                _ => println!("Please enter a valid column number (1-{})", D::WIDTH),
+            }
+        }
+    }
+}
+
+/// Returns the width and height of the board to play on.
+fn determine_board_size() -> Option<(u8, u8)> {
+    println!("Select board size:");
+    println!("1. 7x6 (standard)");
+    println!("2. 8x7");
+    println!("3. 9x7");
+    println!("4. 6x5");
+
+    loop {
         let input = read_input()?;
-        let board = GameState::from_fen(input.as_str(), None);
+
+        if input.is_empty() {
+            println!("Defaulting to 7x6.");
+            return Some((7, 6));
+        }
+
+        match input.parse() {
+            Ok(1) => return Some((7, 6)),
+            Ok(2) => return Some((8, 7)),
+            Ok(3) => return Some((9, 7)),
+            Ok(4) => return Some((6, 5)),
+            _ => println!("Invalid option, select 1, 2, 3, or 4"),
+        }
+    }
+}
+
+/// Ask how the game should be won.
+fn determine_rules() -> Option<Rules> {
+    let mut rules = Rules {
+        connect: determine_connect()?,
+        ..Rules::default()
+    };
+
+    println!("Would you like to play with PopOut rules? (y/n)");
+    rules.popout = read_input()? == "y";
+
+    println!("Would you like to play misère, where completing a line loses? (y/n)");
+    rules.misere = read_input()? == "y";
+
+    rules.cylinder = determine_cylinder()?;
+    Some(rules)
+}
+
+/// Ask how a three-player game should be won, which only has some of the two-player rules.
+fn determine_three_player_rules() -> Option<Rules> {
+    Some(Rules {
+        connect: determine_connect()?,
+        cylinder: determine_cylinder()?,
+        ..Rules::default()
+    })
+}
+
+/// Ask how many pieces in a row win the game.
+fn determine_connect() -> Option<u8> {
+    let default = Rules::default().connect;
+    println!(
+        "How many pieces in a row are needed to win? (default {})",
+        default
+    );
+    loop {
+        let input = read_input()?;
+        if input.is_empty() {
+            return Some(default);
+        }
+        match input.parse() {
+            Ok(connect @ 2..=8) => return Some(connect),
+            _ => println!("Please enter a number from 2 to 8"),
+        }
+    }
+}
+
+fn determine_cylinder() -> Option<bool> {
+    println!("Would you like to play on a cylinder, where lines wrap around the sides? (y/n)");
+    Some(read_input()? == "y")
+}
+
+fn load_game<D: Dimensions>(rules: Rules) -> Option<GameState<D>> {
+    println!("Would you like to load a game? (y for FEN, m for a move sequence such as 4453342, n for a new game)");
+    let input = read_input()?;
+
+    let board = if input == "y" {
+        println!("Enter FEN string, optionally followed by the side to move, starting player and ply (e.g. r y 1):");
+        let board = loop {
+            let board = match GameState::from_fen_with_rules(read_input()?.as_str(), None, rules) {
+                Ok(board) => board,
+                Err(error) => {
+                    println!("Invalid FEN, {}. Please try again:", error);
+                    continue;
+                }
+            };
+            // Pops can undo lines and change the piece counts, so only positions without them can be checked
+            match board.check_reachable() {
+                Err(reason) if !rules.popout => println!(
+                    "This position can't come up in a real game, {}. Please try again:",
+                    reason
+                ),
+                _ => break board,
+            }
+        };
         println!("Loaded game state from FEN:");
-        println!("{:?}", board);
-        Some(board)
-    } else if input == "q" {
-        return None;
+        board
+    } else if input == "m" {
+        println!("Enter the columns played, starting with {}:", Color::Yellow);
+        // Move sequences are standard notation, so they are checked under the standard rules
+        let mut board = loop {
+            match GameState::from_moves(&read_input()?) {
+                Ok(board) => break board,
+                Err(error) => println!("Invalid move sequence, {}. Please try again:", error),
+            }
+        };
+        board.rules = rules;
+        println!("Loaded game state from move sequence:");
+        board
     } else {
         println!("Starting a new game.");
-        Some(GameState::new())
+        // A loaded position already says whose turn it is, so only a new game asks who starts
+        let mut board = GameState::with_rules(rules);
+        override_starting_color(&mut board)?;
+        return Some(board);
+    };
+
+    println!("{:?}", board);
+    // The proof search cannot tell when a position repeats, so it can't handle PopOut rules.
+    // Positions that are already over are reported once the game starts, and have nothing to prove.
+    if !board.rules.popout && board.classify() == Classification::Ongoing {
+        offer_proof(&board)?;
     }
+    Some(board)
+}
+
+/// Offer to solve a loaded position with proof-number search before playing it.
+fn offer_proof<D: Dimensions>(board: &GameState<D>) -> Option<()> {
+    println!("Would you like to prove the result of this position? (y/n)");
+    if read_input()? != "y" {
+        return Some(());
+    }
+    let result = solve(board, PROOF_NODE_LIMIT);
+    println!(
+        "{} to play: {:?} (proof tree of {} nodes, {} nodes searched)",
+        board.current_player, result.verdict, result.proof_size, result.nodes_searched
+    );
+    Some(())
 }
 
-fn override_starting_color(board: &mut GameState) -> Option<()> {
+fn override_starting_color<D: Dimensions>(board: &mut GameState<D>) -> Option<()> {
     println!(
         "Would you like {} or {} to play first? (y/r)",
         Color::Yellow,
@@ -140,92 +889,231 @@ fn read_input() -> Option<String> {
     Some(input)
 }
 
-fn make_computer_turn(board: &mut GameState, transposition_table: &mut transposition::Table) {
-    println!("{} Computer's turn", board.current_player);
-    let (column, eval) = engine::negamax_entrypoint(board, transposition_table);
-    if board.make_move(column as u8) {
-        println!(
-            "{} plays column {} with eval of {}",
-            board.current_player.opposite(),
-            column + 1, // Convert to 1-indexed for display
-            eval
-        );
-    } else {
-        panic!("Computer tried to play in a full column: {}", column + 1);
+/// Read a file name exactly as typed, so its case is kept and a file can be called 'q'.
+/// Returns `None` once there is no more input.
+fn read_path() -> Option<String> {
+    let mut input = String::new();
+    let read = std::io::stdin()
+        .read_line(&mut input)
+        .expect("Failed to read input");
+    if read == 0 {
+        println!("Exiting game.");
+        return None;
     }
+    Some(input.trim_end_matches(['\n', '\r']).to_string())
 }
 
-/// If all goes well, returns a ControlFlow::Continue(false) to switch to playing against the bot.
-/// If the user wants to quit, returns ControlFlow::Break(()).
-/// If the user wants to switch to playing against the bot, returns ControlFlow::Continue(true).
-fn make_human_turn(board: &mut GameState) -> ControlFlow<(), bool> {
-    println!("{} Human's turn", board.current_player);
-    println!("Enter column number (1-7) or 'q' to quit or 's' to switch to playing against bot:");
-    let input = match read_input() {
-        Some(input) => input,
-        None => return ControlFlow::Break(()), // User wants to quit
-    };
-    if input == "s" {
-        return ControlFlow::Continue(true);
+/// A person entering moves at the terminal.
+struct HumanPlayer;
+
+impl<D: Dimensions> Player<GameState<D>> for HumanPlayer {
+    fn name(&self) -> &str {
+        "Human"
+    }
+
+    fn is_human(&self) -> bool {
+        true
     }
 
-    // User inputs 1-indexed column
-    match input.parse::<u8>() {
-        Ok(column) if column < 8 && column > 0 => {
-            let is_full = board.make_move(column - 1);
-            if is_full {
-                println!("Column {} is full!", column);
+    fn choose_move(&mut self, board: &GameState<D>) -> ControlFlow<Interrupt, Move> {
+        if board.rules.popout {
+            println!(
+                "Enter column number (1-{}), 'p' followed by a column number to pop, or 'q' to quit, 's' to swap sides with the computer, 'u' to undo, 'r' to redo, 'save' to save the game or 'load' to load one:",
+                D::WIDTH
+            );
+        } else {
+            println!(
+                "Enter column number (1-{}) or 'q' to quit, 's' to swap sides with the computer, 'u' to undo, 'r' to redo, 'save' to save the game or 'load' to load one:",
+                D::WIDTH
+            );
+        }
+        loop {
+            let input = match read_input() {
+                Some(input) => input,
+                None => return ControlFlow::Break(Interrupt::Quit),
+            };
+            match input.as_str() {
+                "s" => return ControlFlow::Break(Interrupt::SwapSides),
+                "u" => return ControlFlow::Break(Interrupt::Undo),
+                "r" => return ControlFlow::Break(Interrupt::Redo),
+                "save" => return ControlFlow::Break(Interrupt::Save),
+                "load" => return ControlFlow::Break(Interrupt::Load),
+                _ => {}
+            }
+
+            // Pops are entered as 'p' followed by the column
+            let (popping, column) = match input.strip_prefix('p') {
+                Some(column) if board.rules.popout => (true, column.trim()),
+                _ => (false, input.as_str()),
+            };
+
+            // User inputs 1-indexed column
+            match column.parse::<u8>() {
+                Ok(column) if column <= D::WIDTH && column > 0 => {
+                    if popping {
+                        if board.can_pop(column - 1) {
+                            return ControlFlow::Continue(Move::Pop(column - 1));
+                        }
+                        println!("You don't have a piece at the bottom of column {}!", column);
+                    } else {
+                        if board.can_play(column - 1) {
+                            return ControlFlow::Continue(Move::Drop(column - 1));
+                        }
+                        println!("Column {} is full!", column);
+                    }
+                }
+                _ => println!("Please enter a valid column number (1-{})", D::WIDTH),
             }
         }
-        _ => println!("Please enter a valid column number (1-7)"),
     }
-    ControlFlow::Continue(false)
 }
 
-fn determine_gamemode() -> Option<(Gamemode, Color)> {
+/// Returns the kind of player for Yellow and for Red, out of `kinds`.
+fn determine_gamemode(kinds: &[PlayerKind]) -> Option<(PlayerKind, PlayerKind)> {
     println!("Select game mode:");
     println!("1. Human vs Human");
     println!("2. Human vs Computer");
     println!("3. Computer vs Computer");
+    println!("4. Custom");
 
-    let gamemode = loop {
+    let choice = loop {
         let input = read_input()?;
 
         if input.is_empty() {
             println!("Defaulting to Human vs Computer.");
-            break Gamemode::PlayerVsComputer;
+            break 2;
         }
 
         match input.parse() {
-            Ok(1) => break Gamemode::PlayerVsPlayer,
-            Ok(2) => break Gamemode::PlayerVsComputer,
-            Ok(3) => break Gamemode::ComputerVsComputer,
+            Ok(choice @ 1..=4) => break choice,
             _ => {
-                println!("Invalid option, select 1, 2, or 3");
+                println!("Invalid option, select 1, 2, 3, or 4");
                 continue;
             }
         };
     };
 
-    let player_color = if gamemode == Gamemode::PlayerVsComputer {
-        // get user input for player color
-        println!(
-            "Do you want to play as {} or {} (y/r)?",
-            Color::Yellow,
-            Color::Red
-        );
-        let input = read_input()?;
-        match input.as_str() {
-            "y" | "yellow" => Color::Yellow,
-            "r" | "red" => Color::Red,
-            _ => {
-                println!("Defaulting to {}", Color::Red);
+    match choice {
+        1 => Some((PlayerKind::Human, PlayerKind::Human)),
+        2 => {
+            // get user input for player color
+            println!(
+                "Do you want to play as {} or {} (y/r)?",
+                Color::Yellow,
                 Color::Red
+            );
+            let input = read_input()?;
+            match input.as_str() {
+                "y" | "yellow" => Some((PlayerKind::Human, PlayerKind::Computer)),
+                "r" | "red" => Some((PlayerKind::Computer, PlayerKind::Human)),
+                _ => {
+                    println!("Defaulting to {}", Color::Red);
+                    Some((PlayerKind::Computer, PlayerKind::Human))
+                }
             }
         }
-    } else {
-        Color::Yellow // Default color for Player vs Player and Computer vs Computer, doesn't matter
+        3 => Some((PlayerKind::Computer, PlayerKind::Computer)),
+        _ => Some((
+            determine_player_kind(Color::Yellow, kinds)?,
+            determine_player_kind(Color::Red, kinds)?,
+        )),
+    }
+}
+
+fn determine_player_kind(color: impl fmt::Display, kinds: &[PlayerKind]) -> Option<PlayerKind> {
+    println!("Who should play as {}?", color);
+    for (number, kind) in kinds.iter().enumerate() {
+        println!("{}. {}", number + 1, kind.description());
+    }
+
+    loop {
+        let input = read_input()?;
+        match input.parse::<usize>() {
+            Ok(choice @ 1..) if choice <= kinds.len() => return Some(kinds[choice - 1]),
+            _ => println!("Invalid option, select a number from 1 to {}", kinds.len()),
+        }
+    }
+}
+
+/// Ask how strongly the computer should play.
+/// The question is also asked when only humans play, since a human may swap sides with the computer.
+fn determine_difficulty() -> Option<EngineConfig> {
+    println!("Select computer difficulty:");
+    println!("1. Easy");
+    println!("2. Medium");
+    println!("3. Hard");
+    println!("4. Perfect");
+
+    let difficulty = loop {
+        let input = read_input()?;
+
+        if input.is_empty() {
+            println!("Defaulting to Perfect.");
+            break Difficulty::Perfect;
+        }
+
+        match input.parse() {
+            Ok(1) => break Difficulty::Easy,
+            Ok(2) => break Difficulty::Medium,
+            Ok(3) => break Difficulty::Hard,
+            Ok(4) => break Difficulty::Perfect,
+            _ => {
+                println!("Invalid option, select 1, 2, 3, or 4");
+                continue;
+            }
+        };
     };
 
-    Some((gamemode, player_color))
+    let mut engine_config = EngineConfig::from_difficulty(difficulty);
+
+    println!("Should the computer pick randomly between equally good moves? (y/n)");
+    engine_config.randomize_ties = read_input()? == "y";
+
+    Some(engine_config)
+}
+
+/// Ask how long the Monte Carlo computer may think about each move.
+/// A time limit makes it easy to compare it with the negamax computer at the same budget.
+fn determine_mcts_budget() -> Option<MctsConfig> {
+    let mut mcts_config = MctsConfig::default();
+    println!(
+        "Enter the Monte Carlo computer's time limit per move in seconds, or press enter for {} playouts:",
+        mcts_config.iterations
+    );
+    loop {
+        let input = read_input()?;
+        if input.is_empty() {
+            return Some(mcts_config);
+        }
+        match input.parse::<f64>() {
+            Ok(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                // The time limit alone decides when to stop
+                mcts_config.iterations = u32::MAX;
+                mcts_config.time_limit = Some(Duration::from_secs_f64(seconds));
+                return Some(mcts_config);
+            }
+            _ => println!("Please enter a positive number of seconds or press enter"),
+        }
+    }
+}
+
+/// Ask for the seed of the computer's random choices.
+/// Entering the seed printed by an earlier game replays that game's choices.
+fn determine_seed() -> Option<u64> {
+    println!("Enter a random seed to replay a game, or press enter for a new seed:");
+    loop {
+        let input = read_input()?;
+        if input.is_empty() {
+            return Some(
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .expect("system time to be after the unix epoch")
+                    .as_nanos() as u64,
+            );
+        }
+        match input.parse() {
+            Ok(seed) => return Some(seed),
+            Err(_) => println!("Please enter a whole number or press enter"),
+        }
+    }
 }
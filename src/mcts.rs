@@ -0,0 +1,255 @@
+use crate::color::{Color, Gameover};
+use crate::dimensions::Dimensions;
+use crate::gamestate::{GameState, Move, Moves, MAX_MOVES};
+use rand_mt::Mt64;
+use rayon::prelude::*;
+use std::time::{Duration, Instant};
+use tinyvec::ArrayVec;
+
+/// Settings for the Monte Carlo Tree Search engine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MctsConfig {
+    /// Total number of playouts, shared between all threads
+    pub iterations: u32,
+    /// Stop early once this much time has passed, even if there are iterations left
+    pub time_limit: Option<Duration>,
+    /// UCT exploration constant, higher values explore less visited moves more often
+    pub exploration: f64,
+    /// Let playouts take an immediate win whenever one is available, instead of moving completely at random
+    pub biased_playouts: bool,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        MctsConfig {
+            iterations: 200_000,
+            time_limit: None,
+            exploration: std::f64::consts::SQRT_2,
+            biased_playouts: true,
+        }
+    }
+}
+
+struct Node {
+    /// Move played to reach this node from its parent
+    mv: Move,
+    /// Player who played `mv`
+    mover: Color,
+    children: ArrayVec<[usize; MAX_MOVES]>,
+    untried: Moves,
+    visits: u32,
+    /// Sum of playout results from the point of view of `mover`, 1 for a win and 0.5 for a tie
+    reward: f64,
+    gameover: Gameover,
+}
+
+impl Node {
+    fn new<D: Dimensions>(board: &GameState<D>, mv: Move) -> Self {
+        let gameover = board.gameover_state();
+        Node {
+            mv,
+            mover: board.current_player.opposite(),
+            children: ArrayVec::new(),
+            untried: if gameover == Gameover::None {
+                board.legal_moves()
+            } else {
+                ArrayVec::new()
+            },
+            visits: 0,
+            reward: 0.0,
+            gameover,
+        }
+    }
+
+    fn uct(&self, parent_visits: u32, exploration: f64) -> f64 {
+        let visits = self.visits as f64;
+        self.reward / visits + exploration * ((parent_visits as f64).ln() / visits).sqrt()
+    }
+}
+
+/// Search the `board` with MCTS/UCT and return the best move with its estimated chance of winning.
+///
+/// Every rayon thread grows its own tree from a seed derived from `seed`,
+/// and the visit counts of the root moves are summed at the end.
+pub fn search<D: Dimensions>(board: &GameState<D>, config: &MctsConfig, seed: u64) -> (Move, f64) {
+    let moves = board.legal_moves();
+    let threads = rayon::current_num_threads() as u32;
+    let deadline = config.time_limit.map(|limit| Instant::now() + limit);
+
+    let root_stats: Vec<Vec<(u32, f64)>> = (0..threads)
+        .into_par_iter()
+        .map(|thread| {
+            // Hand out the iterations that don't divide evenly to the first threads
+            let iterations =
+                config.iterations / threads + u32::from(thread < config.iterations % threads);
+            let mut rng = Mt64::new(seed.wrapping_add(thread as u64));
+            grow_tree(board, config, iterations, deadline, &mut rng)
+        })
+        .collect();
+
+    let mut totals = vec![(0u32, 0f64); moves.len()];
+    for stats in root_stats {
+        for (total, (visits, reward)) in totals.iter_mut().zip(stats) {
+            total.0 += visits;
+            total.1 += reward;
+        }
+    }
+
+    let (&mv, &(visits, reward)) = moves
+        .iter()
+        .zip(totals.iter())
+        .max_by_key(|(_, (visits, _))| *visits)
+        .expect("Must have at least one valid move");
+    let win_rate = if visits == 0 {
+        0.5
+    } else {
+        reward / visits as f64
+    };
+    (mv, win_rate)
+}
+
+/// Run up to `iterations` of select, expand, simulate and backpropagate on a fresh tree.
+/// Returns the (visits, reward) of each root move, in the order of `board.legal_moves()`.
+fn grow_tree<D: Dimensions>(
+    board: &GameState<D>,
+    config: &MctsConfig,
+    iterations: u32,
+    deadline: Option<Instant>,
+    rng: &mut Mt64,
+) -> Vec<(u32, f64)> {
+    let mut tree = vec![Node::new(board, Move::default())];
+    let mut path = Vec::new();
+
+    for _ in 0..iterations {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break;
+        }
+
+        let mut board = board.clone();
+        path.clear();
+        path.push(0);
+
+        // Selection: walk down fully expanded nodes
+        let mut node = 0;
+        while tree[node].untried.is_empty() && !tree[node].children.is_empty() {
+            let parent_visits = tree[node].visits;
+            node = *tree[node]
+                .children
+                .iter()
+                .max_by(|&&a, &&b| {
+                    let a = tree[a].uct(parent_visits, config.exploration);
+                    let b = tree[b].uct(parent_visits, config.exploration);
+                    a.total_cmp(&b)
+                })
+                .expect("Fully expanded nodes must have children");
+            board.play(tree[node].mv);
+            path.push(node);
+        }
+
+        // Expansion: add one untried move as a new child
+        if !tree[node].untried.is_empty() {
+            let index = (rng.next_u64() % tree[node].untried.len() as u64) as usize;
+            let mv = tree[node].untried.swap_remove(index);
+            board.play(mv);
+            tree.push(Node::new(&board, mv));
+            let child = tree.len() - 1;
+            tree[node].children.push(child);
+            node = child;
+            path.push(node);
+        }
+
+        // Simulation: play the game out from the new node
+        let gameover = if tree[node].gameover == Gameover::None {
+            playout(&mut board, config.biased_playouts, rng)
+        } else {
+            tree[node].gameover
+        };
+
+        // Backpropagation: credit every node on the path from its mover's point of view
+        for &node in path.iter() {
+            let node = &mut tree[node];
+            node.visits += 1;
+            node.reward += match gameover {
+                Gameover::Win(color) if color == node.mover => 1.0,
+                Gameover::Tie => 0.5,
+                _ => 0.0,
+            };
+        }
+    }
+
+    board
+        .legal_moves()
+        .iter()
+        .map(|&mv| {
+            tree[0]
+                .children
+                .iter()
+                .map(|&child| &tree[child])
+                .find(|child| child.mv == mv)
+                .map_or((0, 0.0), |child| (child.visits, child.reward))
+        })
+        .collect()
+}
+
+/// Play random moves until the game is over and return the result.
+/// Under PopOut rules a game can go on forever, so playouts that run too long are scored as a tie.
+fn playout<D: Dimensions>(board: &mut GameState<D>, biased: bool, rng: &mut Mt64) -> Gameover {
+    for _ in 0..4 * D::CELLS {
+        let moves = board.legal_moves();
+        let mover = board.current_player;
+        let winning_move = if biased {
+            moves.iter().copied().find(|&mv| {
+                board.play(mv);
+                let wins = board.gameover_state() == Gameover::Win(mover);
+                board.unplay(mv);
+                wins
+            })
+        } else {
+            None
+        };
+        let mv =
+            winning_move.unwrap_or_else(|| moves[(rng.next_u64() % moves.len() as u64) as usize]);
+        board.play(mv);
+
+        let gameover = board.gameover_state();
+        if gameover != Gameover::None {
+            return gameover;
+        }
+    }
+    Gameover::Tie
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> MctsConfig {
+        MctsConfig {
+            iterations: 20_000,
+            ..MctsConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_mcts_takes_immediate_win() {
+        let board: GameState = GameState::from_fen(
+            "......./......./......./......./rrr..../yyy....",
+            Some(Color::Yellow),
+        )
+        .unwrap();
+        let (mv, win_rate) = search(&board, &test_config(), 1);
+        assert_eq!(mv, Move::Drop(3));
+        assert!(win_rate > 0.9);
+    }
+
+    #[test]
+    fn test_mcts_blocks_opponent_win() {
+        let board: GameState = GameState::from_fen(
+            "......./......./......./......./......./yrrr.yy",
+            Some(Color::Yellow),
+        )
+        .unwrap();
+        let (mv, _) = search(&board, &test_config(), 1);
+        assert_eq!(mv, Move::Drop(4));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::color::{Color, Gameover};
+use crate::dimensions::{Bitboard, Dimensions};
+use crate::gamestate::GameState;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Why a move sequence such as "4453342" could not be replayed.
+/// Positions count from 0 within the sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveSequenceError {
+    /// The character is not a column on this board
+    InvalidColumn { position: usize, character: char },
+    /// The column was already full
+    ColumnFull { position: usize, column: u8 },
+    /// The game had already ended before this move
+    GameOver { position: usize },
+}
+
+impl fmt::Display for MoveSequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Moves and columns are displayed 1-indexed
+        match self {
+            MoveSequenceError::InvalidColumn {
+                position,
+                character,
+            } => write!(f, "move {}: '{}' is not a column", position + 1, character),
+            MoveSequenceError::ColumnFull { position, column } => {
+                write!(f, "move {}: column {} is full", position + 1, column + 1)
+            }
+            MoveSequenceError::GameOver { position } => {
+                write!(f, "move {}: the game is already over", position + 1)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MoveSequenceError {}
+
+impl<D: Dimensions> GameState<D> {
+    /// Replay a sequence of 1-indexed columns, such as "4453342", from the empty board.
+    /// Yellow moves first, as in [`GameState::new`].
+    pub fn from_moves(moves: &str) -> Result<Self, MoveSequenceError> {
+        let mut game = Self::new();
+        game.play_moves(moves)?;
+        Ok(game)
+    }
+
+    /// Play a sequence of 1-indexed columns from the current position.
+    /// Stops at the first move that can't be played, leaving the moves before it on the board.
+    fn play_moves(&mut self, moves: &str) -> Result<(), MoveSequenceError> {
+        for (position, character) in moves.chars().enumerate() {
+            let column = match character.to_digit(10) {
+                Some(column @ 1..) if column <= D::WIDTH as u32 => column as u8 - 1,
+                _ => {
+                    return Err(MoveSequenceError::InvalidColumn {
+                        position,
+                        character,
+                    })
+                }
+            };
+            if self.gameover_state() != Gameover::None {
+                return Err(MoveSequenceError::GameOver { position });
+            }
+            if !self.make_move(column) {
+                return Err(MoveSequenceError::ColumnFull { position, column });
+            }
+        }
+        Ok(())
+    }
+
+    /// Find a sequence of 1-indexed columns that leads from the empty board to this position,
+    /// with the player who started this game moving first and nobody winning before the last move.
+    ///
+    /// Returns `None` if no such sequence exists, or if the board is too wide for single digit columns.
+    pub fn to_moves(&self) -> Option<String> {
+        if D::WIDTH > 9 {
+            return None;
+        }
+        let mut board = self.clone();
+        let mut columns = Vec::new();
+        let mut dead_ends = HashSet::new();
+        if !Self::unplay_to_start(
+            &mut board,
+            self.starting_player(),
+            &mut columns,
+            &mut dead_ends,
+        ) {
+            return None;
+        }
+        Some(
+            columns
+                .iter()
+                .map(|&column| char::from(b'1' + column))
+                .collect(),
+        )
+    }
+
+    /// Take back pieces one at a time until the board is empty with `starter` to move, trying every column
+    /// whose top piece belongs to the player who moved last. Pushes the columns in the order they were played.
+    /// `dead_ends` remembers positions that can't be taken back, so they are only searched once.
+    pub(crate) fn unplay_to_start(
+        board: &mut Self,
+        starter: Color,
+        columns: &mut Vec<u8>,
+        dead_ends: &mut HashSet<Self>,
+    ) -> bool {
+        if board.filled() == D::Bits::ZERO {
+            return board.current_player == starter;
+        }
+        if dead_ends.contains(board) {
+            return false;
+        }
+
+        let mover = board.current_player.opposite();
+        for column in 0..D::WIDTH {
+            let height = board.get_height(column);
+            if height == 0
+                || board.pieces(mover) & Self::cell_mask(column, height - 1) == D::Bits::ZERO
+            {
+                continue;
+            }
+            board.undo_move(column);
+            // The game must still have been going before this move
+            if board.gameover_state() == Gameover::None
+                && Self::unplay_to_start(board, starter, columns, dead_ends)
+            {
+                columns.push(column);
+                return true;
+            }
+            board.make_move(column);
+        }
+
+        dead_ends.insert(board.clone());
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_moves() {
+        let game: GameState = GameState::from_moves("4453342").unwrap();
+        assert_eq!(
+            game.board_fen(),
+            "......./......./......./...r.../..yr.../.yryy.."
+        );
+        assert_eq!(game.current_player, Color::Red);
+    }
+
+    #[test]
+    fn test_from_moves_rejects_bad_sequences() {
+        assert_eq!(
+            GameState::<crate::dimensions::Standard>::from_moves("448"),
+            Err(MoveSequenceError::InvalidColumn {
+                position: 2,
+                character: '8'
+            })
+        );
+        assert_eq!(
+            GameState::<crate::dimensions::Standard>::from_moves("1111111"),
+            Err(MoveSequenceError::ColumnFull {
+                position: 6,
+                column: 0
+            })
+        );
+        // Yellow wins in column 1 on the 7th move
+        assert_eq!(
+            GameState::<crate::dimensions::Standard>::from_moves("12121213"),
+            Err(MoveSequenceError::GameOver { position: 7 })
+        );
+    }
+
+    #[test]
+    fn test_to_moves_round_trip() {
+        for moves in ["", "4453342", "1212121", "444444333333"] {
+            let game: GameState = GameState::from_moves(moves).unwrap();
+            let replayed: GameState = GameState::from_moves(&game.to_moves().unwrap()).unwrap();
+            assert_eq!(replayed, game, "{}", moves);
+        }
+    }
+
+    #[test]
+    fn test_to_moves_when_red_started() {
+        let mut game: GameState = GameState::new();
+        game.current_player = Color::Red;
+        for column in [3, 3, 2] {
+            game.make_move(column);
+        }
+        assert_eq!(game.starting_player(), Color::Red);
+        assert_eq!(game.to_moves().as_deref(), Some("443"));
+    }
+
+    #[test]
+    fn test_to_moves_rejects_unreachable_positions() {
+        // With Red to move after one ply, Yellow started, so the red piece can't be there
+        let game: GameState = GameState::from_fen(
+            "......./......./......./......./......./...r...",
+            Some(Color::Red),
+        )
+        .unwrap();
+        assert_eq!(game.to_moves(), None);
+        // Yellow has already won, so Red can't have moved after that
+        let game: GameState = GameState::from_fen(
+            "......./......./y....../y....../yr...../yrrr...",
+            Some(Color::Yellow),
+        )
+        .unwrap();
+        assert_eq!(game.to_moves(), None);
+    }
+}
@@ -0,0 +1,336 @@
+use crate::color::{Color, Gameover};
+use crate::dimensions::Dimensions;
+use crate::engine::{self, EngineConfig};
+use crate::gamestate::{GameState, Move};
+use crate::mcts::{self, MctsConfig};
+use crate::transposition;
+use rand_mt::Mt64;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+/// A game that players take turns in, so that the same players can play every variant.
+pub trait TurnBased: Clone {
+    /// Whoever can be to move, such as a [`Color`]
+    type Seat: Copy + Eq + fmt::Display;
+    type Move: Copy + Eq + fmt::Display;
+
+    /// The player to move
+    fn to_move(&self) -> Self::Seat;
+
+    /// Every legal move, in the order engines prefer them
+    fn moves(&self) -> Vec<Self::Move>;
+
+    /// Play a legal move for the player to move
+    fn play_move(&mut self, mv: Self::Move);
+
+    /// The player who has won, if anyone has
+    fn winner(&self) -> Option<Self::Seat>;
+}
+
+impl<D: Dimensions> TurnBased for GameState<D> {
+    type Seat = Color;
+    type Move = Move;
+
+    fn to_move(&self) -> Color {
+        self.current_player
+    }
+
+    fn moves(&self) -> Vec<Move> {
+        self.legal_moves().to_vec()
+    }
+
+    fn play_move(&mut self, mv: Move) {
+        self.play(mv);
+    }
+
+    fn winner(&self) -> Option<Color> {
+        match self.gameover_state() {
+            Gameover::Win(color) => Some(color),
+            Gameover::Tie | Gameover::None => None,
+        }
+    }
+}
+
+/// Why a player stopped choosing moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    /// End the game right away
+    Quit,
+    /// Hand this side over to the computer and take over the other side
+    SwapSides,
+    /// Take back the last move, and any computer moves before it, until a person is to move
+    Undo,
+    /// Replay the last move that was taken back
+    Redo,
+    /// Write the game so far to a game record file
+    Save,
+    /// Replace the game with one read from a game record file
+    Load,
+}
+
+/// What an engine worked out while choosing its last move, for the game loop to show.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Analysis<M = Move> {
+    /// Eval of every legal move from the engine's point of view, best first
+    Evals {
+        evals: Vec<(M, i32)>,
+        /// Entries cleared from a full transposition table before searching
+        cleared_entries: Option<usize>,
+    },
+    /// Estimated chance that the chosen move wins, from 0 to 1
+    WinRate(f64),
+}
+
+/// Anything that can pick moves in a game, whether a person at the terminal or an engine.
+pub trait Player<G: TurnBased> {
+    /// Short description shown in the game log
+    fn name(&self) -> &str;
+
+    /// Whether this player is a person, who may want to swap sides with the computer
+    fn is_human(&self) -> bool {
+        false
+    }
+
+    /// Eval of the last move this player chose, from its own point of view, if it evaluates moves
+    fn last_eval(&self) -> Option<i32> {
+        None
+    }
+
+    /// What the player found out about the position while choosing its last move, if it analyses positions
+    fn last_analysis(&self) -> Option<&Analysis<G::Move>> {
+        None
+    }
+
+    /// Choose a legal move for the player to move.
+    /// Returns `ControlFlow::Break` if the player wants to stop playing instead.
+    fn choose_move(&mut self, board: &G) -> ControlFlow<Interrupt, G::Move>;
+
+    /// Like [`Player::choose_move`], in a game that went through the positions in `history` before `board`.
+    /// Players that need to know which positions repeat use it, and the rest ignore it.
+    fn choose_move_after(&mut self, history: &[G], board: &G) -> ControlFlow<Interrupt, G::Move> {
+        let _ = history;
+        self.choose_move(board)
+    }
+}
+
+/// The exhaustive negamax solver behind [`negamax_entrypoint`](crate::negamax_entrypoint).
+pub struct NegamaxPlayer<D: Dimensions> {
+    table: Arc<transposition::Table<D>>,
+    config: EngineConfig,
+    rng: Mt64,
+    last_eval: Option<i32>,
+    last_analysis: Option<Analysis>,
+}
+
+impl<D: Dimensions> NegamaxPlayer<D> {
+    pub fn new(table: Arc<transposition::Table<D>>, config: EngineConfig, rng: Mt64) -> Self {
+        NegamaxPlayer {
+            table,
+            config,
+            rng,
+            last_eval: None,
+            last_analysis: None,
+        }
+    }
+}
+
+impl<D: Dimensions> Player<GameState<D>> for NegamaxPlayer<D> {
+    fn name(&self) -> &str {
+        "Computer"
+    }
+
+    fn last_eval(&self) -> Option<i32> {
+        self.last_eval
+    }
+
+    fn last_analysis(&self) -> Option<&Analysis> {
+        self.last_analysis.as_ref()
+    }
+
+    fn choose_move(&mut self, board: &GameState<D>) -> ControlFlow<Interrupt, Move> {
+        self.choose_move_after(&[], board)
+    }
+
+    fn choose_move_after(
+        &mut self,
+        history: &[GameState<D>],
+        board: &GameState<D>,
+    ) -> ControlFlow<Interrupt, Move> {
+        let cleared_entries = transposition::check_for_table_clear(&self.table);
+        let evals = engine::evaluate_moves(history, board, &self.table, &self.config);
+        let (mv, eval) = engine::choose_move(&evals, &self.config, &mut self.rng);
+        self.last_eval = Some(eval);
+        self.last_analysis = Some(Analysis::Evals {
+            evals,
+            cleared_entries,
+        });
+        ControlFlow::Continue(mv)
+    }
+}
+
+/// The Monte Carlo Tree Search engine, configured by [`MctsConfig`].
+pub struct MctsPlayer {
+    config: MctsConfig,
+    rng: Mt64,
+    last_analysis: Option<Analysis>,
+}
+
+impl MctsPlayer {
+    pub fn new(config: MctsConfig, rng: Mt64) -> Self {
+        MctsPlayer {
+            config,
+            rng,
+            last_analysis: None,
+        }
+    }
+}
+
+impl<D: Dimensions> Player<GameState<D>> for MctsPlayer {
+    fn name(&self) -> &str {
+        "MCTS"
+    }
+
+    fn last_analysis(&self) -> Option<&Analysis> {
+        self.last_analysis.as_ref()
+    }
+
+    fn choose_move(&mut self, board: &GameState<D>) -> ControlFlow<Interrupt, Move> {
+        let (mv, win_rate) = mcts::search(board, &self.config, self.rng.next_u64());
+        self.last_analysis = Some(Analysis::WinRate(win_rate));
+        ControlFlow::Continue(mv)
+    }
+}
+
+/// Plays a uniformly random legal move.
+pub struct RandomPlayer {
+    rng: Mt64,
+}
+
+impl RandomPlayer {
+    pub fn new(rng: Mt64) -> Self {
+        RandomPlayer { rng }
+    }
+}
+
+impl<G: TurnBased> Player<G> for RandomPlayer {
+    fn name(&self) -> &str {
+        "Random"
+    }
+
+    fn choose_move(&mut self, board: &G) -> ControlFlow<Interrupt, G::Move> {
+        ControlFlow::Continue(pick(&board.moves(), &mut self.rng))
+    }
+}
+
+/// Looks a single ply ahead: takes a win if there is one, otherwise plays a random move that does not hand
+/// the next player a win, which blocks their winning move if it can be blocked.
+pub struct GreedyPlayer {
+    rng: Mt64,
+}
+
+impl GreedyPlayer {
+    pub fn new(rng: Mt64) -> Self {
+        GreedyPlayer { rng }
+    }
+}
+
+impl<G: TurnBased> Player<G> for GreedyPlayer {
+    fn name(&self) -> &str {
+        "Greedy"
+    }
+
+    fn choose_move(&mut self, board: &G) -> ControlFlow<Interrupt, G::Move> {
+        let moves = board.moves();
+        let mover = board.to_move();
+
+        if let Some(&mv) = moves.iter().find(|&&mv| wins(board, mv, mover)) {
+            return ControlFlow::Continue(mv);
+        }
+
+        // Variants with extra turns may leave the same player to move, who can't hand themselves a win
+        let safe_moves: Vec<G::Move> = moves
+            .iter()
+            .filter(|&&mv| {
+                let mut new_board = board.clone();
+                new_board.play_move(mv);
+                let next = new_board.to_move();
+                next == mover
+                    || !new_board
+                        .moves()
+                        .iter()
+                        .any(|&reply| wins(&new_board, reply, next))
+            })
+            .copied()
+            .collect();
+
+        if safe_moves.is_empty() {
+            ControlFlow::Continue(pick(&moves, &mut self.rng))
+        } else {
+            ControlFlow::Continue(pick(&safe_moves, &mut self.rng))
+        }
+    }
+}
+
+/// Whether playing `mv` wins the game for `mover`
+fn wins<G: TurnBased>(board: &G, mv: G::Move, mover: G::Seat) -> bool {
+    let mut new_board = board.clone();
+    new_board.play_move(mv);
+    new_board.winner() == Some(mover)
+}
+
+fn pick<M: Copy>(moves: &[M], rng: &mut Mt64) -> M {
+    assert!(!moves.is_empty(), "Must have at least one valid move");
+    moves[(rng.next_u64() % moves.len() as u64) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_greedy_takes_win_over_block() {
+        // Both players have three on the bottom row, Yellow to play
+        let board: GameState = GameState::from_fen(
+            "......./......./......./......./......./yyy.rrr",
+            Some(crate::color::Color::Yellow),
+        )
+        .unwrap();
+        let mut player = GreedyPlayer::new(Mt64::new_unseeded());
+        assert_eq!(
+            player.choose_move(&board),
+            ControlFlow::Continue(Move::Drop(3))
+        );
+    }
+
+    #[test]
+    fn test_greedy_blocks_opponent_win() {
+        let board: GameState = GameState::from_fen(
+            "......./......./......./......./......./yrrr.yy",
+            Some(crate::color::Color::Yellow),
+        )
+        .unwrap();
+        let mut player = GreedyPlayer::new(Mt64::new_unseeded());
+        assert_eq!(
+            player.choose_move(&board),
+            ControlFlow::Continue(Move::Drop(4))
+        );
+    }
+
+    #[test]
+    fn test_random_player_avoids_full_columns() {
+        let mut board: GameState = GameState::new();
+        for column in 0..6 {
+            for _ in 0..6 {
+                board.make_move(column);
+            }
+        }
+        let mut player = RandomPlayer::new(Mt64::new_unseeded());
+        for _ in 0..10 {
+            assert_eq!(
+                player.choose_move(&board),
+                ControlFlow::Continue(Move::Drop(6))
+            );
+        }
+    }
+}
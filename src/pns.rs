@@ -0,0 +1,300 @@
+use crate::color::{Color, Gameover};
+use crate::dimensions::Dimensions;
+use crate::gamestate::{GameState, Move, MAX_MOVES};
+use tinyvec::ArrayVec;
+
+/// Proof and disproof numbers that can never be reached, marking a node as disproven or proven
+const INFINITY: u32 = u32::MAX;
+
+/// Game-theoretic value of a position for the player to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Verdict {
+    Win,
+    Draw,
+    Loss,
+    /// The search ran out of nodes before it could prove anything
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProofResult {
+    pub verdict: Verdict,
+    /// Number of nodes in the proof tree that establishes the verdict
+    pub proof_size: usize,
+    /// Total number of nodes created across all searches
+    pub nodes_searched: usize,
+}
+
+struct Node {
+    /// Move played to reach this node from its parent
+    mv: Move,
+    parent: Option<usize>,
+    children: ArrayVec<[usize; MAX_MOVES]>,
+    /// OR nodes have the root player to move, AND nodes have their opponent to move
+    or_node: bool,
+    proof: u32,
+    disproof: u32,
+    expanded: bool,
+}
+
+/// Outcome that the search is trying to prove for the player to move at the root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Goal {
+    Win,
+    AtLeastDraw,
+}
+
+/// Solve the `board` with proof-number search.
+///
+/// A proof-number search can only answer yes/no questions, so it first tries to prove a win for the player to move,
+/// then tries to prove that they can at least draw. Each search may create at most `max_nodes` nodes.
+pub fn solve<D: Dimensions>(board: &GameState<D>, max_nodes: usize) -> ProofResult {
+    match board.gameover_state() {
+        Gameover::Win(color) => {
+            // Usually the previous player has won, unless their move completed a line that counts for the player to move
+            return ProofResult {
+                verdict: if color == board.current_player {
+                    Verdict::Win
+                } else {
+                    Verdict::Loss
+                },
+                proof_size: 1,
+                nodes_searched: 1,
+            };
+        }
+        Gameover::Tie => {
+            return ProofResult {
+                verdict: Verdict::Draw,
+                proof_size: 1,
+                nodes_searched: 1,
+            }
+        }
+        Gameover::None => {}
+    }
+
+    let win = ProofSearch::new(board, Goal::Win).run(max_nodes);
+    if win.verdict != Verdict::Loss {
+        // Either the win was proven, or the search gave up
+        return win;
+    }
+
+    let draw = ProofSearch::new(board, Goal::AtLeastDraw).run(max_nodes);
+    ProofResult {
+        verdict: match draw.verdict {
+            Verdict::Win => Verdict::Draw,
+            Verdict::Loss => Verdict::Loss,
+            verdict => verdict,
+        },
+        proof_size: draw.proof_size,
+        nodes_searched: win.nodes_searched + draw.nodes_searched,
+    }
+}
+
+/// A single proof-number search, with OR nodes where the root player moves and AND nodes where the opponent moves.
+struct ProofSearch<D: Dimensions> {
+    root: GameState<D>,
+    root_player: Color,
+    goal: Goal,
+    tree: Vec<Node>,
+}
+
+impl<D: Dimensions> ProofSearch<D> {
+    fn new(board: &GameState<D>, goal: Goal) -> Self {
+        ProofSearch {
+            root: board.clone(),
+            root_player: board.current_player,
+            goal,
+            tree: vec![Node {
+                mv: Move::default(),
+                parent: None,
+                children: ArrayVec::new(),
+                or_node: true,
+                proof: 1,
+                disproof: 1,
+                expanded: false,
+            }],
+        }
+    }
+
+    /// Returns `Win` if the goal was proven and `Loss` if it was disproven.
+    fn run(mut self, max_nodes: usize) -> ProofResult {
+        while self.tree[0].proof != 0 && self.tree[0].disproof != 0 {
+            if self.tree.len() >= max_nodes {
+                return ProofResult {
+                    verdict: Verdict::Unknown,
+                    proof_size: 0,
+                    nodes_searched: self.tree.len(),
+                };
+            }
+            let (node, board) = self.select_most_proving();
+            self.expand(node, &board);
+            self.update_ancestors(node);
+        }
+
+        let proven = self.tree[0].proof == 0;
+        ProofResult {
+            verdict: if proven { Verdict::Win } else { Verdict::Loss },
+            proof_size: self.proof_size(0, proven),
+            nodes_searched: self.tree.len(),
+        }
+    }
+
+    /// Walk down from the root, following the child that is cheapest to (dis)prove.
+    fn select_most_proving(&self) -> (usize, GameState<D>) {
+        let mut board = self.root.clone();
+        let mut node = 0;
+        while self.tree[node].expanded {
+            let children = self.tree[node].children.iter();
+            node = *if self.tree[node].or_node {
+                children.min_by_key(|&&child| self.tree[child].proof)
+            } else {
+                children.min_by_key(|&&child| self.tree[child].disproof)
+            }
+            .expect("Expanded non-terminal nodes must have children");
+            board.play(self.tree[node].mv);
+        }
+        (node, board)
+    }
+
+    fn expand(&mut self, node: usize, board: &GameState<D>) {
+        for mv in board.legal_moves() {
+            let mut child_board = board.clone();
+            child_board.play(mv);
+            let (proof, disproof) = match self.evaluate(&child_board) {
+                Some(true) => (0, INFINITY),
+                Some(false) => (INFINITY, 0),
+                None => (1, 1),
+            };
+            self.tree.push(Node {
+                mv,
+                parent: Some(node),
+                children: ArrayVec::new(),
+                or_node: !self.tree[node].or_node,
+                proof,
+                disproof,
+                expanded: false,
+            });
+            let child = self.tree.len() - 1;
+            self.tree[node].children.push(child);
+        }
+        self.tree[node].expanded = true;
+    }
+
+    /// Whether a finished game meets the goal, or `None` if the game is still going.
+    fn evaluate(&self, board: &GameState<D>) -> Option<bool> {
+        match board.gameover_state() {
+            Gameover::Win(color) => Some(color == self.root_player),
+            Gameover::Tie => Some(self.goal == Goal::AtLeastDraw),
+            Gameover::None => None,
+        }
+    }
+
+    fn update_ancestors(&mut self, mut node: usize) {
+        loop {
+            let children = &self.tree[node].children;
+            let proofs = children.iter().map(|&child| self.tree[child].proof);
+            let disproofs = children.iter().map(|&child| self.tree[child].disproof);
+            let (proof, disproof) = if self.tree[node].or_node {
+                (
+                    proofs.min().unwrap_or(INFINITY),
+                    disproofs.fold(0, u32::saturating_add),
+                )
+            } else {
+                (
+                    proofs.fold(0, u32::saturating_add),
+                    disproofs.min().unwrap_or(INFINITY),
+                )
+            };
+            self.tree[node].proof = proof;
+            self.tree[node].disproof = disproof;
+
+            match self.tree[node].parent {
+                Some(parent) => node = parent,
+                None => break,
+            }
+        }
+    }
+
+    /// Count the nodes of the smallest proof (or disproof) tree below `node`.
+    /// A proof needs one proven child of each OR node and every child of each AND node, and the reverse for a disproof.
+    fn proof_size(&self, node: usize, proven: bool) -> usize {
+        let children = &self.tree[node].children;
+        if children.is_empty() {
+            return 1;
+        }
+        let needs_one_child = self.tree[node].or_node == proven;
+        let solved = |&&child: &&usize| {
+            if proven {
+                self.tree[child].proof == 0
+            } else {
+                self.tree[child].disproof == 0
+            }
+        };
+        let subtree_size = |&child: &usize| self.proof_size(child, proven);
+        if needs_one_child {
+            1 + children
+                .iter()
+                .filter(solved)
+                .map(subtree_size)
+                .min()
+                .expect("Solved nodes must have a solved child")
+        } else {
+            1 + children.iter().map(subtree_size).sum::<usize>()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{self, EngineConfig};
+    use crate::transposition;
+    use rand_mt::Mt64;
+
+    #[test]
+    fn test_proves_immediate_win() {
+        let board: GameState = GameState::from_fen(
+            "......./......./......./......./rrr..../yyy....",
+            Some(Color::Yellow),
+        )
+        .unwrap();
+        let result = solve(&board, 100_000);
+        assert_eq!(result.verdict, Verdict::Win);
+        assert_eq!(result.proof_size, 2);
+    }
+
+    #[test]
+    fn test_verdicts_match_negamax() {
+        let table = transposition::Table::default();
+        let mut rng = Mt64::new(7);
+        let mut checked = 0;
+        while checked < 10 {
+            // Play random moves until only a few squares are left, restarting if someone wins on the way
+            let mut board: GameState = GameState::new();
+            while board.filled().count_ones() < 28 && board.gameover_state() == Gameover::None {
+                let column = (rng.next_u64() % 7) as u8;
+                board.make_move(column);
+            }
+            if board.gameover_state() != Gameover::None {
+                continue;
+            }
+
+            let result = solve(&board, 1_000_000);
+            let (_, eval) = engine::negamax_entrypoint(
+                &board,
+                &table,
+                &EngineConfig::default(),
+                &mut Mt64::new_unseeded(),
+            );
+            let expected = match eval.signum() {
+                1 => Verdict::Win,
+                0 => Verdict::Draw,
+                _ => Verdict::Loss,
+            };
+            assert_eq!(result.verdict, expected, "{:?}", board);
+            checked += 1;
+        }
+    }
+}
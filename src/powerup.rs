@@ -0,0 +1,637 @@
+use crate::color::{Color, Gameover};
+use crate::dimensions::{Bitboard, Dimensions, Standard};
+use crate::engine::WINNING_EVAL;
+use crate::gamestate::{self, GameState, Move};
+use crate::player::{Analysis, Interrupt, Player, TurnBased};
+use colored::*;
+use std::fmt;
+use std::ops::ControlFlow;
+
+/// The special pieces of the Power Up variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Special {
+    /// Smashes every piece in the column and lands at the bottom as a normal piece
+    Anvil,
+    /// Removes one of the opponent's pieces from the bottom of a column, letting the pieces above it fall down
+    Bomb,
+    /// A blocker that can't be part of anyone's line, after which the same player moves again
+    Wall,
+    /// A normal piece, after which the same player moves again
+    Double,
+}
+
+impl Special {
+    pub const ALL: [Special; 4] = [
+        Special::Anvil,
+        Special::Bomb,
+        Special::Wall,
+        Special::Double,
+    ];
+}
+
+impl fmt::Display for Special {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Special::Anvil => write!(f, "anvil"),
+            Special::Bomb => write!(f, "bomb"),
+            Special::Wall => write!(f, "wall"),
+            Special::Double => write!(f, "x2"),
+        }
+    }
+}
+
+/// Special pieces a player has left, everyone starts with one of each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Inventory {
+    pub anvils: u8,
+    pub bombs: u8,
+    pub walls: u8,
+    pub doubles: u8,
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Inventory {
+            anvils: 1,
+            bombs: 1,
+            walls: 1,
+            doubles: 1,
+        }
+    }
+}
+
+impl Inventory {
+    pub fn count(&self, special: Special) -> u8 {
+        match special {
+            Special::Anvil => self.anvils,
+            Special::Bomb => self.bombs,
+            Special::Wall => self.walls,
+            Special::Double => self.doubles,
+        }
+    }
+
+    fn count_mut(&mut self, special: Special) -> &mut u8 {
+        match special {
+            Special::Anvil => &mut self.anvils,
+            Special::Bomb => &mut self.bombs,
+            Special::Wall => &mut self.walls,
+            Special::Double => &mut self.doubles,
+        }
+    }
+}
+
+/// A turn in the Power Up variant, identified by a 0-indexed column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PowerMove {
+    /// A move of the underlying rules
+    Normal(Move),
+    /// Play a special piece into a column
+    Special(Special, u8),
+}
+
+impl PowerMove {
+    pub const fn column(&self) -> u8 {
+        match self {
+            PowerMove::Normal(mv) => mv.column(),
+            PowerMove::Special(_, column) => *column,
+        }
+    }
+}
+
+impl fmt::Display for PowerMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowerMove::Normal(mv) => write!(f, "{}", mv),
+            PowerMove::Special(special, column) => write!(f, "{} column {}", special, column + 1),
+        }
+    }
+}
+
+/// Everything needed to take back a move.
+/// Every move only changes a single column, so that column's pieces are all that has to be kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Undo<D: Dimensions> {
+    mv: PowerMove,
+    mover: Color,
+    ply: u32,
+    red: D::Bits,
+    yellow: D::Bits,
+    walls: D::Bits,
+}
+
+/// A game of Power Up, played on top of a normal [`GameState`].
+///
+/// Walls are stored in the bitboard of the player who played them, so gravity keeps working,
+/// and `walls` marks which of those pieces don't count towards a line.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PowerUpGame<D: Dimensions = Standard> {
+    pub board: GameState<D>,
+    pub walls: D::Bits,
+    /// Yellow's inventory first, then Red's
+    pub inventories: [Inventory; 2],
+}
+
+/// Index of the inventory for `color`
+const fn seat(color: Color) -> usize {
+    match color {
+        Color::Yellow => 0,
+        Color::Red => 1,
+    }
+}
+
+impl<D: Dimensions> PowerUpGame<D> {
+    pub fn new(board: GameState<D>) -> Self {
+        PowerUpGame {
+            board,
+            walls: D::Bits::ZERO,
+            inventories: [Inventory::default(); 2],
+        }
+    }
+
+    #[inline(always)]
+    pub fn current_player(&self) -> Color {
+        self.board.current_player
+    }
+
+    pub fn inventory(&self, color: Color) -> &Inventory {
+        &self.inventories[seat(color)]
+    }
+
+    /// The playable cells of `column`
+    fn column_mask(column: u8) -> D::Bits {
+        D::Bits::from_u128(D::COLUMN_MASK) << (column as u32 * D::STRIDE as u32)
+    }
+
+    /// Remove the bottom cell of `column` from `board`, moving the cells above it down by one
+    fn remove_bottom(board: D::Bits, column: u8) -> D::Bits {
+        let mask = Self::column_mask(column);
+        let above = (board & mask) >> 1 & mask;
+        (board & !mask) | above
+    }
+
+    /// Whether the current player may play `special` into `column`
+    pub fn can_play_special(&self, special: Special, column: u8) -> bool {
+        if column >= D::WIDTH || self.inventory(self.current_player()).count(special) == 0 {
+            return false;
+        }
+        match special {
+            // An anvil into an empty column would just be a normal drop
+            Special::Anvil => self.board.filled() & Self::column_mask(column) != D::Bits::ZERO,
+            Special::Bomb => {
+                // Obstacles never move, so pieces can't fall through them
+                let bottom = GameState::<D>::cell_mask(column, 0);
+                self.board.pieces(self.current_player().opposite()) & bottom & !self.walls
+                    != D::Bits::ZERO
+                    && self.board.obstacles & Self::column_mask(column) == D::Bits::ZERO
+            }
+            Special::Wall | Special::Double => self.board.can_play(column),
+        }
+    }
+
+    /// Whether the current player may play `mv`
+    pub fn is_legal(&self, mv: PowerMove) -> bool {
+        match mv {
+            PowerMove::Normal(Move::Drop(column)) => {
+                column < D::WIDTH && self.board.can_play(column)
+            }
+            PowerMove::Normal(Move::Pop(column)) => {
+                // Walls belong to nobody, so they can't be popped
+                column < D::WIDTH
+                    && self.board.can_pop(column)
+                    && self.walls & GameState::<D>::cell_mask(column, 0) == D::Bits::ZERO
+            }
+            PowerMove::Special(special, column) => self.can_play_special(special, column),
+        }
+    }
+
+    /// Every legal move, with the normal moves first
+    pub fn legal_moves(&self) -> Vec<PowerMove> {
+        let mut moves: Vec<PowerMove> = self
+            .board
+            .legal_moves()
+            .into_iter()
+            .map(PowerMove::Normal)
+            .filter(|&mv| self.is_legal(mv))
+            .collect();
+        for special in Special::ALL {
+            for column in D::column_order() {
+                if self.can_play_special(special, column) {
+                    moves.push(PowerMove::Special(special, column));
+                }
+            }
+        }
+        moves
+    }
+
+    /// Play `mv` for the current player.
+    ///
+    /// Returns what is needed to undo the move, or `None` if the move was not legal.
+    pub fn play(&mut self, mv: PowerMove) -> Option<Undo<D>> {
+        if !self.is_legal(mv) {
+            return None;
+        }
+
+        let mover = self.current_player();
+        let column = mv.column();
+        let mask = Self::column_mask(column);
+        let undo = Undo {
+            mv,
+            mover,
+            ply: self.board.ply,
+            red: self.board.red & mask,
+            yellow: self.board.yellow & mask,
+            walls: self.walls & mask,
+        };
+
+        match mv {
+            PowerMove::Normal(mv) => {
+                self.board.play(mv);
+                if let Move::Pop(_) = mv {
+                    // Walls above the popped piece fall down with everything else
+                    self.walls = Self::remove_bottom(self.walls, column);
+                }
+            }
+            PowerMove::Special(special, _) => {
+                *self.inventories[seat(mover)].count_mut(special) -= 1;
+                match special {
+                    Special::Anvil => {
+                        self.board.red &= !mask;
+                        self.board.yellow &= !mask;
+                        self.walls &= !mask;
+                        self.board.make_move(column);
+                    }
+                    Special::Bomb => {
+                        self.board.red = Self::remove_bottom(self.board.red, column);
+                        self.board.yellow = Self::remove_bottom(self.board.yellow, column);
+                        self.walls = Self::remove_bottom(self.walls, column);
+                        self.board.override_current_player(mover.opposite());
+                        self.board.ply += 1;
+                    }
+                    Special::Wall => {
+                        let height = self.board.get_height(column);
+                        self.board.make_move(column);
+                        self.walls |= GameState::<D>::cell_mask(column, height);
+                        self.board.override_current_player(mover);
+                    }
+                    Special::Double => {
+                        self.board.make_move(column);
+                        self.board.override_current_player(mover);
+                    }
+                }
+            }
+        }
+        Some(undo)
+    }
+
+    /// Take back a move returned by [`PowerUpGame::play`]
+    pub fn unplay(&mut self, undo: Undo<D>) {
+        let mask = Self::column_mask(undo.mv.column());
+        self.board.red = (self.board.red & !mask) | undo.red;
+        self.board.yellow = (self.board.yellow & !mask) | undo.yellow;
+        self.walls = (self.walls & !mask) | undo.walls;
+        self.board.override_current_player(undo.mover);
+        self.board.ply = undo.ply;
+        if let PowerMove::Special(special, _) = undo.mv {
+            *self.inventories[seat(undo.mover)].count_mut(special) += 1;
+        }
+    }
+
+    /// The board without its walls, which can't be part of a line
+    fn lines(&self) -> GameState<D> {
+        let mut lines = self.board.clone();
+        lines.red &= !self.walls;
+        lines.yellow &= !self.walls;
+        lines
+    }
+
+    pub fn gameover_state(&self) -> Gameover {
+        let lines = self.lines();
+
+        // Extra turns and bombs break the usual turn order, so both players are checked.
+        // A player who just took an extra turn is still to move, and only they can have made a new line.
+        // A bomb can complete lines for both players, in which case the player who threw it wins.
+        let current = self.current_player();
+        let line = if lines.has_line(current.opposite()) {
+            Some(current.opposite())
+        } else if lines.has_line(current) {
+            Some(current)
+        } else {
+            None
+        };
+        if let Some(color) = line {
+            return Gameover::Win(self.board.rules.winner_for_line(color));
+        }
+
+        // A full board may still be played on with a bomb or anvil
+        if self.legal_moves().is_empty() {
+            Gameover::Tie
+        } else {
+            Gameover::None
+        }
+    }
+}
+
+impl<D: Dimensions> TurnBased for PowerUpGame<D> {
+    type Seat = Color;
+    type Move = PowerMove;
+
+    fn to_move(&self) -> Color {
+        self.current_player()
+    }
+
+    fn moves(&self) -> Vec<PowerMove> {
+        self.legal_moves()
+    }
+
+    fn play_move(&mut self, mv: PowerMove) {
+        self.play(mv).expect("Legal moves must be playable");
+    }
+
+    fn winner(&self) -> Option<Color> {
+        match self.gameover_state() {
+            Gameover::Win(color) => Some(color),
+            Gameover::Tie | Gameover::None => None,
+        }
+    }
+}
+
+/// The depth-limited Power Up [`power_up_search`], since the usual engines don't know about special pieces.
+pub struct PowerUpPlayer {
+    max_depth: u16,
+    last_analysis: Option<Analysis<PowerMove>>,
+}
+
+impl PowerUpPlayer {
+    pub fn new(max_depth: u16) -> Self {
+        PowerUpPlayer {
+            max_depth,
+            last_analysis: None,
+        }
+    }
+}
+
+impl<D: Dimensions> Player<PowerUpGame<D>> for PowerUpPlayer {
+    fn name(&self) -> &str {
+        "Computer"
+    }
+
+    fn last_eval(&self) -> Option<i32> {
+        match &self.last_analysis {
+            Some(Analysis::Evals { evals, .. }) => evals.first().map(|&(_, eval)| eval),
+            _ => None,
+        }
+    }
+
+    fn last_analysis(&self) -> Option<&Analysis<PowerMove>> {
+        self.last_analysis.as_ref()
+    }
+
+    fn choose_move(&mut self, game: &PowerUpGame<D>) -> ControlFlow<Interrupt, PowerMove> {
+        let evals = evaluate_power_moves(game, self.max_depth);
+        let (mv, _) = evals[0];
+        self.last_analysis = Some(Analysis::Evals {
+            evals,
+            cleared_entries: None,
+        });
+        ControlFlow::Continue(mv)
+    }
+}
+
+/// Search the `game` up to `max_depth` plies and return the best move with its eval.
+pub fn power_up_search<D: Dimensions>(game: &PowerUpGame<D>, max_depth: u16) -> (PowerMove, i32) {
+    evaluate_power_moves(game, max_depth)[0]
+}
+
+/// Eval of every legal move after searching `max_depth` plies, from the point of view of the player to move, best first.
+/// Moves with equal evals keep the order of [`PowerUpGame::legal_moves`], which puts the central normal moves first.
+///
+/// Extra turns mean the same player can move twice in a row, so unlike [`evaluate_moves`](crate::evaluate_moves),
+/// an eval is only negated when the turn actually passes to the opponent.
+pub fn evaluate_power_moves<D: Dimensions>(
+    game: &PowerUpGame<D>,
+    max_depth: u16,
+) -> Vec<(PowerMove, i32)> {
+    let mut game = game.clone();
+    let mover = game.current_player();
+    let mut results = Vec::new();
+    for mv in game.legal_moves() {
+        let undo = game.play(mv).expect("Legal moves must be playable");
+        let eval = score_for(&mut game, mover, -10_000, 10_000, 1, max_depth);
+        game.unplay(undo);
+        results.push((mv, eval));
+    }
+    assert!(!results.is_empty(), "Must have at least one valid move");
+    results.sort_by_key(|result| -result.1);
+    results
+}
+
+/// Eval of `game` from the point of view of `player`, who may or may not be the one to move
+fn score_for<D: Dimensions>(
+    game: &mut PowerUpGame<D>,
+    player: Color,
+    alpha: i32,
+    beta: i32,
+    ply: u16,
+    max_depth: u16,
+) -> i32 {
+    if game.current_player() == player {
+        negamax(game, alpha, beta, ply, max_depth)
+    } else {
+        -negamax(game, -beta, -alpha, ply, max_depth)
+    }
+}
+
+/// Depth-limited negamax from the point of view of the player to move.
+/// Positions cut off by `max_depth` are scored as 0, since their outcome is unknown.
+fn negamax<D: Dimensions>(
+    game: &mut PowerUpGame<D>,
+    mut alpha: i32,
+    beta: i32,
+    ply: u16,
+    max_depth: u16,
+) -> i32 {
+    match game.gameover_state() {
+        // We subtract the ply to encourage winning quickly, and add it to drag out losing games
+        Gameover::Win(color) if color == game.current_player() => return WINNING_EVAL - ply as i32,
+        Gameover::Win(_) => return ply as i32 - WINNING_EVAL,
+        Gameover::Tie => return 0,
+        Gameover::None => {}
+    }
+    if ply >= max_depth {
+        return 0;
+    }
+
+    let player = game.current_player();
+    let mut max_eval = -20_000;
+    for mv in game.legal_moves() {
+        let undo = game.play(mv).expect("Legal moves must be playable");
+        let eval = score_for(game, player, alpha, beta, ply + 1, max_depth);
+        game.unplay(undo);
+
+        max_eval = max_eval.max(eval);
+        alpha = alpha.max(max_eval);
+        if alpha >= beta {
+            break;
+        }
+    }
+    max_eval
+}
+
+impl<D: Dimensions> fmt::Debug for PowerUpGame<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:} to play", self.current_player())?;
+        let highlighted = self.lines().winning_cells();
+        gamestate::write_board::<D>(f, self.board.rules.cylinder, highlighted, |mask| {
+            if self.walls & mask != D::Bits::ZERO {
+                "W".white()
+            } else {
+                self.board.piece_at(mask)
+            }
+        })?;
+
+        for color in [Color::Yellow, Color::Red] {
+            let inventory = self.inventory(color);
+            writeln!(
+                f,
+                "{}: {} anvil, {} bomb, {} wall, {} x2",
+                color, inventory.anvils, inventory.bombs, inventory.walls, inventory.doubles
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game_from_fen(fen: &str, color: Color) -> PowerUpGame {
+        PowerUpGame::new(GameState::from_fen(fen, Some(color)).unwrap())
+    }
+
+    #[test]
+    fn test_anvil_clears_column_and_undoes() {
+        let mut game = game_from_fen(
+            "......./......./......./..r..../..y..../..r....",
+            Color::Yellow,
+        );
+        let before = game.clone();
+        let undo = game.play(PowerMove::Special(Special::Anvil, 2)).unwrap();
+        assert_eq!(
+            game.board.board_fen(),
+            "......./......./......./......./......./..y...."
+        );
+        assert_eq!(game.current_player(), Color::Red);
+        assert_eq!(game.inventory(Color::Yellow).anvils, 0);
+        assert!(!game.can_play_special(Special::Anvil, 3));
+        game.unplay(undo);
+        assert_eq!(game, before);
+    }
+
+    #[test]
+    fn test_bomb_removes_opponent_piece() {
+        let mut game = game_from_fen(
+            "......./......./......./......./..y..../..r.y..",
+            Color::Yellow,
+        );
+        assert!(!game.can_play_special(Special::Bomb, 4)); // Yellow's own piece
+        let before = game.clone();
+        let undo = game.play(PowerMove::Special(Special::Bomb, 2)).unwrap();
+        assert_eq!(
+            game.board.board_fen(),
+            "......./......./......./......./......./..y.y.."
+        );
+        assert_eq!(game.current_player(), Color::Red);
+        game.unplay(undo);
+        assert_eq!(game, before);
+    }
+
+    #[test]
+    fn test_wall_does_not_count_and_gives_extra_turn() {
+        let mut game = game_from_fen(
+            "......./......./......./......./rrr..../yyy....",
+            Color::Yellow,
+        );
+        game.play(PowerMove::Special(Special::Wall, 3)).unwrap();
+        assert_eq!(game.current_player(), Color::Yellow);
+        assert_eq!(game.gameover_state(), Gameover::None);
+    }
+
+    #[test]
+    fn test_walls_cant_be_bombed_or_popped() {
+        let mut game: PowerUpGame = PowerUpGame::new(
+            GameState::from_fen_with_rules(
+                "......./......./......./......./......./.......",
+                Some(Color::Red),
+                crate::rules::Rules {
+                    popout: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        );
+        // Red's wall sits at the bottom of column 4, where Yellow could otherwise bomb it
+        game.play(PowerMove::Special(Special::Wall, 3)).unwrap();
+        game.play(PowerMove::Normal(Move::Drop(0))).unwrap();
+        assert_eq!(game.current_player(), Color::Yellow);
+        assert_ne!(game.board.red & GameState::<Standard>::cell_mask(3, 0), 0);
+        assert!(!game.can_play_special(Special::Bomb, 3));
+        assert!(game.can_play_special(Special::Bomb, 0));
+        // Nor can Red pop it
+        game.play(PowerMove::Normal(Move::Drop(6))).unwrap();
+        assert!(!game.is_legal(PowerMove::Normal(Move::Pop(3))));
+        assert!(game.is_legal(PowerMove::Normal(Move::Pop(0))));
+    }
+
+    #[test]
+    fn test_double_gives_extra_turn() {
+        let mut game = game_from_fen(
+            "......./......./......./......./......./.......",
+            Color::Yellow,
+        );
+        game.play(PowerMove::Special(Special::Double, 3)).unwrap();
+        assert_eq!(game.current_player(), Color::Yellow);
+        assert!(!game
+            .legal_moves()
+            .contains(&PowerMove::Special(Special::Double, 3)));
+    }
+
+    #[test]
+    fn test_greedy_player_wins_power_up_games() {
+        use crate::player::GreedyPlayer;
+        use rand_mt::Mt64;
+
+        let game = game_from_fen(
+            "......./......./......./......./rrr..../yyy....",
+            Color::Yellow,
+        );
+        let mut player = GreedyPlayer::new(Mt64::new(1));
+        let ControlFlow::Continue(mv) = player.choose_move(&game) else {
+            panic!("The greedy player never stops playing");
+        };
+        let mut after = game.clone();
+        after.play(mv).unwrap();
+        assert_eq!(
+            after.gameover_state(),
+            Gameover::Win(Color::Yellow),
+            "{}",
+            mv
+        );
+    }
+
+    #[test]
+    fn test_search_wins_with_double() {
+        // Yellow can't win with a single piece, but can with an x2 followed by another drop
+        let game = game_from_fen(
+            "......./......./......./......./r....../rr.yy..",
+            Color::Yellow,
+        );
+        let (mv, eval) = power_up_search(&game, 2);
+        assert!(
+            matches!(mv, PowerMove::Special(Special::Double, 2 | 5)),
+            "{}",
+            mv
+        );
+        assert!(eval > 0);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::color::Color;
+use crate::dimensions::{Bitboard, Dimensions};
+use crate::gamestate::GameState;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Why a position can't come up in a game played from the empty board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unreachable {
+    /// The pieces overlap each other or an obstacle, or lie outside the board
+    NotABoard,
+    /// A piece with an empty cell below it, counting rows from 0 at the bottom
+    FloatingPiece { column: u8, row: u8 },
+    /// One player has more than one piece more than the other
+    PieceCountMismatch { yellow: u32, red: u32 },
+    /// The player with more pieces must have started, so it can't be their turn
+    WrongPlayerToMove,
+    /// Every order of playing the pieces has someone complete a line before the last move
+    PlayedAfterGameOver,
+}
+
+impl fmt::Display for Unreachable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Rows and columns are displayed 1-indexed
+        match self {
+            Unreachable::NotABoard => write!(f, "the pieces overlap or lie outside the board"),
+            Unreachable::FloatingPiece { column, row } => write!(
+                f,
+                "the piece in column {}, row {} from the bottom has nothing below it",
+                column + 1,
+                row + 1
+            ),
+            Unreachable::PieceCountMismatch { yellow, red } => write!(
+                f,
+                "{} has {} pieces and {} has {}, but the players take turns",
+                Color::Yellow,
+                yellow,
+                Color::Red,
+                red
+            ),
+            Unreachable::WrongPlayerToMove => {
+                write!(f, "the player with more pieces can't be the one to move")
+            }
+            Unreachable::PlayedAfterGameOver => {
+                write!(f, "every way to reach it continues after someone has won")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Unreachable {}
+
+impl<D: Dimensions> GameState<D> {
+    /// Build the position with these pieces and check that it can be reached, see [`GameState::check_reachable`].
+    /// The player to move is worked out from the piece counts.
+    /// If they are equal, either player may have started, so Yellow is tried first and then Red.
+    pub fn reachable_from_bitboards(yellow: D::Bits, red: D::Bits) -> Result<Self, Unreachable> {
+        let mut game = Self::new();
+        game.yellow = yellow;
+        game.red = red;
+        game.ply = (yellow | red).count_ones();
+        if red.count_ones() < yellow.count_ones() {
+            game.current_player = Color::Red;
+        }
+        let result = game.check_reachable();
+        if result.is_err() && red.count_ones() == yellow.count_ones() {
+            game.current_player = Color::Red;
+            if game.check_reachable().is_ok() {
+                return Ok(game);
+            }
+        }
+        result.map(|()| game)
+    }
+
+    /// Check that this position can come up in a game played from the empty board, or from the obstacles alone.
+    ///
+    /// Whoever has more pieces must have started, and with equal counts it is the starter's turn.
+    /// Pops are never considered, so this judges positions as if only drops were allowed.
+    pub fn check_reachable(&self) -> Result<(), Unreachable> {
+        let full_board = D::Bits::from_u128(D::FULL_BOARD_MASK);
+        if self.yellow & self.red != D::Bits::ZERO
+            || self.filled() & self.obstacles != D::Bits::ZERO
+            || (self.filled() | self.obstacles) & !full_board != D::Bits::ZERO
+        {
+            return Err(Unreachable::NotABoard);
+        }
+        if let Some((column, row)) = self.floating_piece() {
+            return Err(Unreachable::FloatingPiece { column, row });
+        }
+
+        let yellow = self.yellow.count_ones();
+        let red = self.red.count_ones();
+        let starter = match yellow as i64 - red as i64 {
+            0 => self.current_player,
+            1 => Color::Yellow,
+            -1 => Color::Red,
+            _ => return Err(Unreachable::PieceCountMismatch { yellow, red }),
+        };
+        if yellow != red && self.current_player == starter {
+            return Err(Unreachable::WrongPlayerToMove);
+        }
+
+        // Take the pieces back in every possible order, looking for one where the game was still going
+        // before each move. Only the last move may complete a line.
+        let mut board = self.clone();
+        let mut columns = Vec::new();
+        let mut dead_ends = HashSet::new();
+        if Self::unplay_to_start(&mut board, starter, &mut columns, &mut dead_ends) {
+            Ok(())
+        } else {
+            Err(Unreachable::PlayedAfterGameOver)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dimensions::Standard;
+
+    #[test]
+    fn test_positions_from_play_are_reachable() {
+        for moves in ["", "4", "4453342", "1212121"] {
+            let game: GameState = GameState::from_moves(moves).unwrap();
+            assert_eq!(game.check_reachable(), Ok(()), "{}", moves);
+        }
+        // Red may have started
+        let game: GameState = GameState::from_fen(
+            "......./......./......./......./......./...r... y r 1",
+            None,
+        )
+        .unwrap();
+        assert_eq!(game.check_reachable(), Ok(()));
+    }
+
+    #[test]
+    fn test_play_after_a_win_is_unreachable() {
+        // Yellow's line is complete and Red moved after it
+        let game: GameState = GameState::from_fen(
+            "......./......./y....../y....../yr...../yrrr...",
+            Some(Color::Yellow),
+        )
+        .unwrap();
+        assert_eq!(
+            game.check_reachable(),
+            Err(Unreachable::PlayedAfterGameOver)
+        );
+
+        // Completing the middle of a long line last is fine
+        let game: GameState =
+            GameState::from_fen("......./......./......./......./rrr.rrr/yyyyyyy", None).unwrap();
+        assert_eq!(game.check_reachable(), Ok(()));
+
+        // One move can't complete two separate lines
+        let game: GameState =
+            GameState::from_fen("......./......./y.....y/yr...ry/yr...ry/yr.r.ry", None).unwrap();
+        assert_eq!(
+            game.check_reachable(),
+            Err(Unreachable::PlayedAfterGameOver)
+        );
+    }
+
+    #[test]
+    fn test_bitboards_are_checked() {
+        let cell = GameState::<Standard>::cell_mask;
+        assert!(GameState::<Standard>::reachable_from_bitboards(cell(3, 0), 0).is_ok());
+        assert_eq!(
+            GameState::<Standard>::reachable_from_bitboards(cell(3, 0), cell(3, 0)),
+            Err(Unreachable::NotABoard)
+        );
+        assert_eq!(
+            GameState::<Standard>::reachable_from_bitboards(cell(3, 1), 0),
+            Err(Unreachable::FloatingPiece { column: 3, row: 1 })
+        );
+        assert_eq!(
+            GameState::<Standard>::reachable_from_bitboards(cell(0, 0) | cell(1, 0), 0),
+            Err(Unreachable::PieceCountMismatch { yellow: 2, red: 0 })
+        );
+        // The sentinel row is outside the board
+        assert_eq!(
+            GameState::<Standard>::reachable_from_bitboards(cell(0, 6), 0),
+            Err(Unreachable::NotABoard)
+        );
+    }
+
+    #[test]
+    fn test_bitboards_where_red_started() {
+        // Yellow just completed column 1, which only works if Red moved first
+        let game: GameState =
+            GameState::from_fen("......./......./y....../y....../yrr..../yrr....", None).unwrap();
+        let reached =
+            GameState::<Standard>::reachable_from_bitboards(game.yellow, game.red).unwrap();
+        assert_eq!(reached.current_player, Color::Red);
+        assert_eq!(reached.starting_player(), Color::Red);
+    }
+
+    #[test]
+    fn test_wrong_player_to_move() {
+        let game: GameState = GameState::from_fen(
+            "......./......./......./......./......./...y...",
+            Some(Color::Yellow),
+        )
+        .unwrap();
+        assert_eq!(game.check_reachable(), Err(Unreachable::WrongPlayerToMove));
+    }
+}
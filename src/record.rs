@@ -0,0 +1,527 @@
+use crate::color::{Color, Gameover};
+use crate::dimensions::Dimensions;
+use crate::game::Game;
+use crate::gamestate::{Classification, FenError, GameState, Move};
+use crate::rules::Rules;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Why a game record could not be read or replayed.
+/// Lines and plies count from 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordError {
+    /// A header line that isn't written as `[Name "value"]`
+    BadHeader { line: usize },
+    /// A token in the move list that isn't a move, move number or result
+    BadMove { token: String },
+    /// A comment that is never closed with `}`
+    UnclosedComment,
+    /// A comment before the first move, which has no move to belong to
+    StrayComment,
+    /// The `[%eval]` annotation of a comment is not a number
+    BadEval { text: String },
+    /// The starting position in the FEN header is invalid
+    Fen(FenError),
+    /// The rules header isn't written like `connect 4, popout`
+    BadRules { text: String },
+    /// A move in the list can't be played in the position it was recorded in
+    IllegalMove { ply: usize, mv: Move },
+    /// The list goes on after the game was already over
+    MoveAfterGameOver { ply: usize },
+    /// The starting position in the FEN header is already won or tied
+    FinishedStart,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Lines and plies are displayed 1-indexed
+        match self {
+            RecordError::BadHeader { line } => write!(f, "line {} is not a valid header", line + 1),
+            RecordError::BadMove { token } => write!(f, "'{}' is not a move", token),
+            RecordError::UnclosedComment => write!(f, "a comment is missing its closing '}}'"),
+            RecordError::StrayComment => write!(f, "a comment comes before the first move"),
+            RecordError::BadEval { text } => write!(f, "'{}' is not an eval", text),
+            RecordError::Fen(error) => write!(f, "invalid starting FEN, {}", error),
+            RecordError::BadRules { text } => write!(f, "'{}' are not valid rules", text),
+            RecordError::IllegalMove { ply, mv } => {
+                write!(f, "move {}: {} is not a legal move", ply + 1, mv)
+            }
+            RecordError::MoveAfterGameOver { ply } => {
+                write!(f, "move {}: the game is already over", ply + 1)
+            }
+            RecordError::FinishedStart => write!(f, "the starting position is already over"),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+/// A move in a game record, with what was said about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMove {
+    pub mv: Move,
+    /// Eval reported by the engine that chose the move, from its own point of view
+    pub eval: Option<i32>,
+    pub comment: Option<String>,
+}
+
+/// A finished or unfinished game in a PGN-like text format, for archiving games.
+///
+/// Headers come first, one per line as `[Name "value"]`, followed by a blank line and the move list.
+/// Moves are 1-indexed columns, with `p` in front for pops, numbered every two plies like `1. 4 4 2. 3`.
+/// A move may be followed by a comment in braces, which starts with `[%eval N]` if the move has an eval.
+/// The list ends with the result: `1-0` if Yellow won, `0-1` if Red won, `1/2-1/2` for a tie,
+/// or `*` for a game still in progress.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GameRecord {
+    /// Header names and values, in the order they are written
+    pub headers: Vec<(String, String)>,
+    pub moves: Vec<RecordedMove>,
+}
+
+impl GameRecord {
+    pub const YELLOW_HEADER: &'static str = "Yellow";
+    pub const RED_HEADER: &'static str = "Red";
+    pub const ENGINE_HEADER: &'static str = "Engine";
+    pub const DATE_HEADER: &'static str = "Date";
+    pub const FEN_HEADER: &'static str = "FEN";
+    pub const RESULT_HEADER: &'static str = "Result";
+    pub const RULES_HEADER: &'static str = "Rules";
+    pub const SEED_HEADER: &'static str = "Seed";
+
+    /// Record the moves of `game`, with headers for the starting position, rules, result and today's date.
+    pub fn new<D: Dimensions>(game: &Game<D>) -> Self {
+        let mut record = GameRecord {
+            headers: Vec::new(),
+            moves: game
+                .moves()
+                .iter()
+                .map(|&mv| RecordedMove {
+                    mv,
+                    eval: None,
+                    comment: None,
+                })
+                .collect(),
+        };
+        record.set_header(Self::DATE_HEADER, &today());
+        record.set_header(Self::FEN_HEADER, &game.start().to_fen());
+        record.set_header(Self::RULES_HEADER, &game.start().rules.to_string());
+        record.set_header(Self::RESULT_HEADER, result_text(game.result()));
+        record
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Change the value of the header `name`, or add it at the end if it isn't there yet
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        match self.headers.iter_mut().find(|(header, _)| header == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.headers.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Read a record written by [`GameRecord`]'s `Display` implementation.
+    pub fn parse(text: &str) -> Result<Self, RecordError> {
+        let mut record = GameRecord::default();
+        let mut lines = text.lines().enumerate().peekable();
+        while let Some((line, header)) = lines.next_if(|(_, text)| text.trim().starts_with('[')) {
+            let (name, value) =
+                parse_header(header.trim()).ok_or(RecordError::BadHeader { line })?;
+            record.headers.push((name, value));
+        }
+
+        let move_text: Vec<&str> = lines.map(|(_, text)| text).collect();
+        let move_text = move_text.join("\n");
+        let mut chars = move_text.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            if c == '{' {
+                chars.next();
+                let mut comment = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => comment.push(c),
+                        None => return Err(RecordError::UnclosedComment),
+                    }
+                }
+                let last = record.moves.last_mut().ok_or(RecordError::StrayComment)?;
+                let (eval, comment) = parse_comment(&comment)?;
+                last.eval = eval;
+                last.comment = comment;
+                continue;
+            }
+
+            let mut token = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '{') {
+                token.push(c);
+            }
+            if is_move_number(&token) || ["1-0", "0-1", "1/2-1/2", "*"].contains(&token.as_str()) {
+                continue;
+            }
+            let mv = parse_move(&token).ok_or(RecordError::BadMove { token })?;
+            record.moves.push(RecordedMove {
+                mv,
+                eval: None,
+                comment: None,
+            });
+        }
+        Ok(record)
+    }
+
+    /// Set up the starting position from the FEN header, or the empty board without one,
+    /// and play the recorded moves on it.
+    /// The game is played under the rules header, or under `rules` for records without one.
+    pub fn replay<D: Dimensions>(&self, rules: Rules) -> Result<Game<D>, RecordError> {
+        let rules = match self.header(Self::RULES_HEADER) {
+            Some(text) => parse_rules(text).ok_or(RecordError::BadRules {
+                text: text.to_string(),
+            })?,
+            None => rules,
+        };
+        let start = match self.header(Self::FEN_HEADER) {
+            Some(fen) => {
+                GameState::from_fen_with_rules(fen, None, rules).map_err(RecordError::Fen)?
+            }
+            None => GameState::with_rules(rules),
+        };
+        // The FEN may have been written by hand, so check both players rather than only the one who just moved.
+        // After that, every position comes from a move and the usual check is enough.
+        if start.classify() != Classification::Ongoing {
+            return Err(RecordError::FinishedStart);
+        }
+        let mut game = Game::new(start);
+        for (ply, recorded) in self.moves.iter().enumerate() {
+            let repeated = rules.popout && game.repetitions() >= Rules::REPETITIONS_FOR_DRAW;
+            if game.board().gameover_state() != Gameover::None || repeated {
+                return Err(RecordError::MoveAfterGameOver { ply });
+            }
+            game.play(recorded.mv)
+                .map_err(|_| RecordError::IllegalMove {
+                    ply,
+                    mv: recorded.mv,
+                })?;
+        }
+        Ok(game)
+    }
+
+    /// Result from the result header, or `None` if it is missing or unknown
+    pub fn result(&self) -> Option<Gameover> {
+        match self.header(Self::RESULT_HEADER)? {
+            "1-0" => Some(Gameover::Win(Color::Yellow)),
+            "0-1" => Some(Gameover::Win(Color::Red)),
+            "1/2-1/2" => Some(Gameover::Tie),
+            "*" => Some(Gameover::None),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.headers.iter() {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{} \"{}\"]", name, value)?;
+        }
+        writeln!(f)?;
+
+        let mut tokens = Vec::new();
+        for (ply, recorded) in self.moves.iter().enumerate() {
+            if ply % 2 == 0 {
+                tokens.push(format!("{}.", ply / 2 + 1));
+            }
+            tokens.push(match recorded.mv {
+                // Columns are written 1-indexed
+                Move::Drop(column) => (column + 1).to_string(),
+                Move::Pop(column) => format!("p{}", column + 1),
+            });
+            let mut comment = Vec::new();
+            if let Some(eval) = recorded.eval {
+                comment.push(format!("[%eval {}]", eval));
+            }
+            if let Some(text) = &recorded.comment {
+                // Braces would end the comment early
+                comment.push(text.replace(['{', '}'], ""));
+            }
+            if !comment.is_empty() {
+                tokens.push(format!("{{{}}}", comment.join(" ")));
+            }
+        }
+        tokens.push(self.header(Self::RESULT_HEADER).unwrap_or("*").to_string());
+        writeln!(f, "{}", tokens.join(" "))
+    }
+}
+
+/// Result as written in the result header and at the end of the move list
+const fn result_text(gameover: Gameover) -> &'static str {
+    match gameover {
+        Gameover::Win(Color::Yellow) => "1-0",
+        Gameover::Win(Color::Red) => "0-1",
+        Gameover::Tie => "1/2-1/2",
+        Gameover::None => "*",
+    }
+}
+
+/// Parse `[Name "value"]`, undoing the escapes the writer adds to the value
+fn parse_header(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(if c == '\\' { chars.next()? } else { c });
+    }
+    Some((name.to_string(), unescaped))
+}
+
+/// Parse rules as written by [`Rules`]'s `Display` implementation, such as `connect 5, popout, cylinder`
+fn parse_rules(text: &str) -> Option<Rules> {
+    let mut parts = text.split(',').map(str::trim);
+    let connect = parts.next()?.strip_prefix("connect ")?.parse().ok()?;
+    let mut rules = Rules {
+        connect,
+        popout: false,
+        misere: false,
+        cylinder: false,
+    };
+    for part in parts {
+        match part {
+            "popout" => rules.popout = true,
+            "misere" => rules.misere = true,
+            "cylinder" => rules.cylinder = true,
+            _ => return None,
+        }
+    }
+    Some(rules)
+}
+
+/// Split a comment into its eval annotation and the remaining text
+fn parse_comment(comment: &str) -> Result<(Option<i32>, Option<String>), RecordError> {
+    let comment = comment.trim();
+    let (eval, text) = match comment.strip_prefix("[%eval") {
+        Some(rest) => {
+            let (eval, text) = rest.split_once(']').ok_or(RecordError::BadEval {
+                text: rest.to_string(),
+            })?;
+            let eval = eval.trim().parse().map_err(|_| RecordError::BadEval {
+                text: eval.trim().to_string(),
+            })?;
+            (Some(eval), text.trim())
+        }
+        None => (None, comment),
+    };
+    Ok((eval, (!text.is_empty()).then(|| text.to_string())))
+}
+
+/// Move numbers such as `12.`, which are only there for people reading the record
+fn is_move_number(token: &str) -> bool {
+    token
+        .strip_suffix('.')
+        .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Parse a 1-indexed column, with `p` in front for a pop
+fn parse_move(token: &str) -> Option<Move> {
+    let (popping, column) = match token.strip_prefix('p') {
+        Some(column) => (true, column),
+        None => (false, token),
+    };
+    let column = column.parse::<u8>().ok()?.checked_sub(1)?;
+    Some(if popping {
+        Move::Pop(column)
+    } else {
+        Move::Drop(column)
+    })
+}
+
+/// Today's date in UTC, written as `YYYY.MM.DD` like PGN dates
+fn today() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    // Convert days since 1970-01-01 to a civil date, counting years from March so leap days come last
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut game: Game = Game::default();
+        for column in [3, 3, 2, 4] {
+            game.play(Move::Drop(column)).unwrap();
+        }
+        let mut record = GameRecord::new(&game);
+        record.set_header(GameRecord::YELLOW_HEADER, "Human");
+        record.set_header(GameRecord::RED_HEADER, "Computer \"hard\"");
+        record.moves[1].eval = Some(-3);
+        record.moves[2].comment = Some("A quiet move".to_string());
+        record.moves[3].eval = Some(12);
+        record.moves[3].comment = Some("Blocks the threat".to_string());
+
+        let text = record.to_string();
+        assert!(text.ends_with(
+            "1. 4 4 {[%eval -3]} 2. 3 {A quiet move} 5 {[%eval 12] Blocks the threat} *\n"
+        ));
+        let parsed = GameRecord::parse(&text).unwrap();
+        assert_eq!(parsed, record);
+        assert_eq!(
+            parsed.header(GameRecord::RED_HEADER),
+            Some("Computer \"hard\"")
+        );
+        assert_eq!(
+            parsed
+                .replay::<crate::dimensions::Standard>(Rules::default())
+                .unwrap()
+                .board(),
+            game.board()
+        );
+    }
+
+    #[test]
+    fn test_record_from_position_with_result() {
+        let start: GameState =
+            GameState::from_fen("......./......./......./......./......./yyy.rrr", None).unwrap();
+        let mut game = Game::new(start);
+        game.play(Move::Drop(3)).unwrap();
+        let record = GameRecord::new(&game);
+        assert_eq!(record.result(), Some(Gameover::Win(Color::Yellow)));
+
+        let text = record.to_string();
+        assert!(text.contains("[FEN \"......./......./......./......./......./yyy.rrr y y 6\"]"));
+        assert!(text.ends_with("1. 4 1-0\n"));
+        let replayed: Game = GameRecord::parse(&text)
+            .unwrap()
+            .replay(Rules::default())
+            .unwrap();
+        assert_eq!(replayed.start(), game.start());
+        assert_eq!(replayed.board(), game.board());
+    }
+
+    #[test]
+    fn test_record_errors() {
+        assert_eq!(
+            GameRecord::parse("[Yellow Human]\n\n1. 4 *"),
+            Err(RecordError::BadHeader { line: 0 })
+        );
+        assert_eq!(
+            GameRecord::parse("1. 4 x *"),
+            Err(RecordError::BadMove {
+                token: "x".to_string()
+            })
+        );
+        assert_eq!(
+            GameRecord::parse("1. 4 {never closed"),
+            Err(RecordError::UnclosedComment)
+        );
+        assert_eq!(
+            GameRecord::parse("{too early} 1. 4 *"),
+            Err(RecordError::StrayComment)
+        );
+        let record = GameRecord::parse("1. 4 p4 *").unwrap();
+        assert_eq!(
+            record
+                .replay::<crate::dimensions::Standard>(Rules::default())
+                .err(),
+            Some(RecordError::IllegalMove {
+                ply: 1,
+                mv: Move::Pop(3)
+            })
+        );
+        // Yellow has four in column 1 after the 7th move
+        let record = GameRecord::parse("1. 1 2 2. 1 2 3. 1 2 4. 1 2 *").unwrap();
+        assert_eq!(
+            record
+                .replay::<crate::dimensions::Standard>(Rules::default())
+                .err(),
+            Some(RecordError::MoveAfterGameOver { ply: 7 })
+        );
+        // Red already has four on the bottom row, but is to move, so only checking both players notices
+        let record = GameRecord::parse(
+            "[FEN \"......./......./......./......./yyy..../rrrry.. r r 8\"]\n\n1. 6 *",
+        )
+        .unwrap();
+        assert_eq!(
+            record
+                .replay::<crate::dimensions::Standard>(Rules::default())
+                .err(),
+            Some(RecordError::FinishedStart)
+        );
+        let record = GameRecord::parse("[Rules \"connect four\"]\n\n1. 4 *").unwrap();
+        assert_eq!(
+            record
+                .replay::<crate::dimensions::Standard>(Rules::default())
+                .err(),
+            Some(RecordError::BadRules {
+                text: "connect four".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_record_keeps_its_rules() {
+        let rules = Rules {
+            connect: 3,
+            popout: true,
+            cylinder: true,
+            ..Rules::default()
+        };
+        let mut game: Game = Game::new(GameState::with_rules(rules));
+        for mv in [Move::Drop(3), Move::Drop(4), Move::Pop(3)] {
+            game.play(mv).unwrap();
+        }
+        let text = GameRecord::new(&game).to_string();
+        assert!(text.contains("[Rules \"connect 3, popout, cylinder\"]"));
+        // The header wins over the rules the record is loaded under
+        let replayed: Game = GameRecord::parse(&text)
+            .unwrap()
+            .replay(Rules::default())
+            .unwrap();
+        assert_eq!(replayed.board().rules, rules);
+        assert_eq!(replayed.board(), game.board());
+    }
+
+    #[test]
+    fn test_repetition_draw_is_recorded_as_tie() {
+        let mut start: GameState = GameState::from_fen(
+            "......./......./......./......./......./y.....r",
+            Some(Color::Yellow),
+        )
+        .unwrap();
+        start.rules.popout = true;
+        let mut game = Game::new(start);
+        for _ in 0..2 {
+            for mv in [Move::Pop(0), Move::Pop(6), Move::Drop(0), Move::Drop(6)] {
+                game.play(mv).unwrap();
+            }
+        }
+        let record = GameRecord::new(&game);
+        assert_eq!(record.result(), Some(Gameover::Tie));
+        assert!(record.to_string().ends_with(" 1/2-1/2\n"));
+    }
+}
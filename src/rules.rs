@@ -0,0 +1,63 @@
+use crate::color::Color;
+use std::fmt;
+
+/// Rule variations that apply on top of the board dimensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rules {
+    /// Number of pieces in a row needed to win
+    pub connect: u8,
+    /// PopOut: instead of dropping a piece, a player may remove one of their own pieces from the bottom of a column
+    pub popout: bool,
+    /// Misère: completing a line loses the game instead of winning it
+    pub misere: bool,
+    /// Cylindrical board: the first and last columns are neighbours, so horizontal and diagonal lines wrap around
+    pub cylinder: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            connect: 4,
+            popout: false,
+            misere: false,
+            cylinder: false,
+        }
+    }
+}
+
+impl fmt::Display for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "connect {}", self.connect)?;
+        for (name, enabled) in [
+            ("popout", self.popout),
+            ("misere", self.misere),
+            ("cylinder", self.cylinder),
+        ] {
+            if enabled {
+                write!(f, ", {}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Rules {
+    /// Under PopOut rules, the game is drawn once the same position has come up this many times
+    pub const REPETITIONS_FOR_DRAW: usize = 3;
+
+    /// Fewest pieces on the board before someone can have a line, assuming the players alternate turns.
+    /// The player who moved first needs `connect` pieces, by which time the other player has one fewer.
+    pub const fn min_pieces_for_win(&self) -> u32 {
+        2 * self.connect as u32 - 1
+    }
+
+    /// Winner of the game once `color` has completed a line, which under misère rules is the other player
+    pub const fn winner_for_line(&self, color: Color) -> Color {
+        if self.misere {
+            color.opposite()
+        } else {
+            color
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Serde support for positions, behind the `serde` feature.
+//!
+//! Human-readable formats such as JSON get the extended FEN, which people can read and paste back into the game.
+//! Binary formats get the raw bitboards, which are smaller and quicker to load.
+//! Both are checked like any other FEN when they are read back.
+
+use crate::color::Color;
+use crate::dimensions::{Bitboard, Dimensions};
+use crate::gamestate::{FenError, GameState};
+use crate::rules::Rules;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "GameState")]
+struct FenRepr {
+    fen: String,
+    rules: Rules,
+}
+
+/// Bitboards are widened to `u128`, so every board size shares the same layout
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "GameState")]
+struct BitboardRepr {
+    red: u128,
+    yellow: u128,
+    obstacles: u128,
+    current_player: Color,
+    ply: u32,
+    rules: Rules,
+}
+
+impl<D: Dimensions> Serialize for GameState<D> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            FenRepr {
+                fen: self.to_fen(),
+                rules: self.rules,
+            }
+            .serialize(serializer)
+        } else {
+            BitboardRepr {
+                red: self.red.to_u128(),
+                yellow: self.yellow.to_u128(),
+                obstacles: self.obstacles.to_u128(),
+                current_player: self.current_player,
+                ply: self.ply,
+                rules: self.rules,
+            }
+            .serialize(serializer)
+        }
+    }
+}
+
+impl<'de, D: Dimensions> Deserialize<'de> for GameState<D> {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        let (fen, rules) = if deserializer.is_human_readable() {
+            let repr = FenRepr::deserialize(deserializer)?;
+            (repr.fen, repr.rules)
+        } else {
+            let repr = BitboardRepr::deserialize(deserializer)?;
+            // The FEN only has room for one thing in each cell of the board, so check the bits it can't show first
+            let board = D::FULL_BOARD_MASK;
+            if (repr.red | repr.yellow | repr.obstacles) & !board != 0 {
+                return Err(De::Error::custom("a bitboard has bits outside the board"));
+            }
+            if repr.red & repr.yellow != 0 || (repr.red | repr.yellow) & repr.obstacles != 0 {
+                return Err(De::Error::custom("a cell is filled more than once"));
+            }
+            let mut game = GameState::<D>::new();
+            game.red = D::Bits::from_u128(repr.red);
+            game.yellow = D::Bits::from_u128(repr.yellow);
+            game.obstacles = D::Bits::from_u128(repr.obstacles);
+            game.current_player = repr.current_player;
+            game.ply = repr.ply;
+            if let Some((column, row)) = game.floating_piece() {
+                return Err(De::Error::custom(FenError::FloatingPiece { column, row }));
+            }
+            // Going through the FEN runs the rest of the checks
+            (game.to_fen(), repr.rules)
+        };
+        GameState::from_fen_with_rules(&fen, None, rules).map_err(De::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Gameover;
+    use crate::dimensions::Dims;
+    use crate::gamestate::Move;
+
+    #[test]
+    fn test_game_state_as_json() {
+        let mut game: GameState = GameState::from_moves("4453").unwrap();
+        game.rules.popout = true;
+        let json = serde_json::to_string(&game).unwrap();
+        assert_eq!(
+            json,
+            r#"{"fen":"......./......./......./......./...r.../..ryy.. y y 4","rules":{"connect":4,"popout":true,"misere":false,"cylinder":false}}"#
+        );
+        assert_eq!(serde_json::from_str::<GameState>(&json).unwrap(), game);
+    }
+
+    #[test]
+    fn test_game_state_as_bitboards() {
+        let mut game = GameState::<Dims<3, 2>>::from_fen("#yr/.ry", Some(Color::Yellow)).unwrap();
+        game.rules.connect = 3;
+        let bytes = postcard::to_allocvec(&game).unwrap();
+        // Three varint bitboards, the side to move, the ply count and the four rules
+        assert_eq!(bytes, [136, 1, 80, 2, 0, 4, 3, 0, 0, 0]);
+        assert_eq!(
+            postcard::from_bytes::<GameState<Dims<3, 2>>>(&bytes).unwrap(),
+            game
+        );
+    }
+
+    #[test]
+    fn test_popout_positions_are_checked_under_popout_rules() {
+        // Yellow has popped out both of their pieces, which is only possible under PopOut rules
+        let mut game: GameState = GameState::from_moves("4545").unwrap();
+        game.rules.popout = true;
+        for mv in [Move::Pop(3), Move::Drop(4), Move::Pop(3)] {
+            assert!(game.play(mv));
+        }
+        let json = serde_json::to_string(&game).unwrap();
+        assert_eq!(serde_json::from_str::<GameState>(&json).unwrap(), game);
+        let bytes = postcard::to_allocvec(&game).unwrap();
+        assert_eq!(postcard::from_bytes::<GameState>(&bytes).unwrap(), game);
+    }
+
+    #[test]
+    fn test_invalid_positions_are_rejected() {
+        let json = r#"{"fen":"......./......./......./......./......./yyy....","rules":{"connect":4,"popout":false,"misere":false,"cylinder":false}}"#;
+        let error = serde_json::from_str::<GameState>(json).unwrap_err();
+        assert!(
+            error.to_string().contains("players take turns"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_invalid_bitboards_are_rejected() {
+        let game = GameState::<Dims<3, 2>>::from_fen("#yr/.ry", Some(Color::Yellow)).unwrap();
+        let repr = |red: u128, yellow: u128| BitboardRepr {
+            red,
+            yellow,
+            obstacles: 0,
+            current_player: Color::Red,
+            ply: 1,
+            rules: game.rules,
+        };
+        let parse = |repr: BitboardRepr| {
+            let bytes = postcard::to_allocvec(&repr).unwrap();
+            postcard::from_bytes::<GameState<Dims<3, 2>>>(&bytes).map(|_| ())
+        };
+        assert!(parse(repr(0, 1)).is_ok());
+        // The sentinel row above the first column
+        assert!(parse(repr(1 << 2, 1)).is_err());
+        assert!(parse(repr(1, 1)).is_err());
+        // A piece in the second row of an empty column
+        assert!(parse(repr(0, 1 << 4)).is_err());
+    }
+
+    #[test]
+    fn test_results_as_json() {
+        assert_eq!(
+            serde_json::to_string(&Gameover::Win(Color::Red)).unwrap(),
+            r#"{"Win":"Red"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&(Move::Pop(2), 7)).unwrap(),
+            r#"[{"Pop":2},7]"#
+        );
+    }
+}
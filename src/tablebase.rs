@@ -0,0 +1,306 @@
+//! Complete win/draw/loss tables for small boards, made by retrograde analysis.
+//!
+//! Without pops every move adds a piece, so a position only leads to positions with one more piece.
+//! [`Tablebase::generate`] starts from the full board and works back towards the empty one, solving every position
+//! with each piece count from the results for the count above it. Positions are stored at their
+//! [`PositionIndex`] rank, two bits each, so looking one up needs no search and no hashing.
+
+use crate::color::{Color, Gameover};
+use crate::dimensions::{Bitboard, Dimensions};
+use crate::gamestate::GameState;
+use crate::indexing::PositionIndex;
+use crate::pns::Verdict;
+use crate::rules::Rules;
+use rayon::prelude::*;
+use std::io::{self, Read, Write};
+
+/// Positions packed into each byte of a layer
+const POSITIONS_PER_BYTE: usize = 4;
+
+/// The value of every position on a `D` board under some rules, for the player to move.
+pub struct Tablebase<D: Dimensions> {
+    rules: Rules,
+    index: PositionIndex<D>,
+    /// `layers[pieces]` holds the verdicts of the positions with that many pieces, in rank order
+    layers: Vec<Vec<u8>>,
+}
+
+impl<D: Dimensions> Tablebase<D> {
+    /// Solve every position on the board under `rules`.
+    ///
+    /// This visits every way to colour every stack of pieces, so it is only practical on small boards such as 4x4
+    /// or 5x4. Panics under PopOut rules, where pops take pieces off the board and positions can repeat.
+    pub fn generate(rules: Rules) -> Self {
+        assert!(!rules.popout, "Tablebases can't be made under PopOut rules");
+        let index = PositionIndex::new();
+        let mut layers = vec![Vec::new(); D::CELLS as usize + 1];
+        for pieces in (0..=D::CELLS).rev() {
+            let count = layer_size(&index, pieces);
+            // The full board has no moves, so it never looks at the layer above
+            let above = layers
+                .get(pieces as usize + 1)
+                .map_or(&[][..], Vec::as_slice);
+            let verdicts: Vec<Verdict> = (0..count)
+                .into_par_iter()
+                .map(|rank| {
+                    let mut board = index
+                        .unrank(pieces, rank as u128)
+                        .expect("every rank below the count to be a position");
+                    board.rules = rules;
+                    solve(&index, &mut board, above)
+                })
+                .collect();
+            layers[pieces as usize] = pack(&verdicts);
+        }
+        Tablebase {
+            rules,
+            index,
+            layers,
+        }
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    /// Value of `board` for the player to move.
+    ///
+    /// Positions from games that Red started are looked up with the colours swapped, which doesn't change who wins.
+    /// Returns `None` for positions under other rules, with obstacles, or that no game could reach one move at a time.
+    pub fn probe(&self, board: &GameState<D>) -> Option<Verdict> {
+        if board.rules != self.rules {
+            return None;
+        }
+        let pieces = board.filled().count_ones();
+        // Yellow is to move after an even number of moves if they started
+        let rank = if (board.current_player == Color::Yellow) == pieces.is_multiple_of(2) {
+            self.index.rank(board)?
+        } else {
+            self.index.rank(&swap_colours(board))?
+        };
+        Some(lookup(&self.layers[pieces as usize], rank as usize))
+    }
+
+    /// Write the tablebase: the board size and rules, followed by the packed verdicts of every layer.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&header::<D>(self.rules))?;
+        for layer in self.layers.iter() {
+            writer.write_all(layer)?;
+        }
+        Ok(())
+    }
+
+    /// Read a tablebase written by [`Tablebase::write`] for the same board size.
+    pub fn read(mut reader: impl Read) -> io::Result<Self> {
+        let mut fields = [0; 5];
+        reader.read_exact(&mut fields)?;
+        let [width, height, connect, misere, cylinder] = fields;
+        if (width, height) != (D::WIDTH, D::HEIGHT) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "tablebase is for a {}x{} board, not {}x{}",
+                    width,
+                    height,
+                    D::WIDTH,
+                    D::HEIGHT
+                ),
+            ));
+        }
+        let rules = Rules {
+            connect,
+            popout: false,
+            misere: misere != 0,
+            cylinder: cylinder != 0,
+        };
+
+        let index = PositionIndex::new();
+        let mut layers = Vec::with_capacity(D::CELLS as usize + 1);
+        for pieces in 0..=D::CELLS {
+            let mut layer = vec![0; layer_size(&index, pieces).div_ceil(POSITIONS_PER_BYTE)];
+            reader.read_exact(&mut layer)?;
+            layers.push(layer);
+        }
+        Ok(Tablebase {
+            rules,
+            index,
+            layers,
+        })
+    }
+}
+
+/// Number of positions with `pieces` pieces, which must fit in memory
+fn layer_size<D: Dimensions>(index: &PositionIndex<D>, pieces: u32) -> usize {
+    usize::try_from(index.count(pieces)).expect("tablebase layers to fit in memory")
+}
+
+/// Board size and rules at the start of a tablebase file
+fn header<D: Dimensions>(rules: Rules) -> [u8; 5] {
+    [
+        D::WIDTH,
+        D::HEIGHT,
+        rules.connect,
+        u8::from(rules.misere),
+        u8::from(rules.cylinder),
+    ]
+}
+
+/// Value of `board` for the player to move, given the verdicts of the positions with one more piece
+fn solve<D: Dimensions>(
+    index: &PositionIndex<D>,
+    board: &mut GameState<D>,
+    above: &[u8],
+) -> Verdict {
+    match board.gameover_state() {
+        // Only possible under misère rules, where the previous player completed their own line
+        Gameover::Win(color) if color == board.current_player => return Verdict::Win,
+        Gameover::Win(_) => return Verdict::Loss,
+        Gameover::Tie => return Verdict::Draw,
+        Gameover::None => {}
+    }
+
+    let mut best = Verdict::Loss;
+    for mv in board.legal_moves() {
+        board.play(mv);
+        let rank = index
+            .rank(board)
+            .expect("a move from a ranked position to be ranked");
+        board.unplay(mv);
+        // A loss for the opponent is a win for the player who moved
+        match lookup(above, rank as usize) {
+            Verdict::Loss => return Verdict::Win,
+            Verdict::Draw => best = Verdict::Draw,
+            _ => {}
+        }
+    }
+    best
+}
+
+/// The same position with Yellow's and Red's pieces swapped, and the other player to move
+fn swap_colours<D: Dimensions>(board: &GameState<D>) -> GameState<D> {
+    let mut swapped = board.clone();
+    swapped.yellow = board.red;
+    swapped.red = board.yellow;
+    swapped.current_player = board.current_player.opposite();
+    swapped
+}
+
+fn pack(verdicts: &[Verdict]) -> Vec<u8> {
+    verdicts
+        .chunks(POSITIONS_PER_BYTE)
+        .map(|chunk| {
+            chunk.iter().enumerate().fold(0, |byte, (slot, &verdict)| {
+                byte | verdict_bits(verdict) << (2 * slot)
+            })
+        })
+        .collect()
+}
+
+fn lookup(layer: &[u8], rank: usize) -> Verdict {
+    let byte = layer[rank / POSITIONS_PER_BYTE];
+    match byte >> (2 * (rank % POSITIONS_PER_BYTE)) & 0b11 {
+        0 => Verdict::Loss,
+        1 => Verdict::Draw,
+        2 => Verdict::Win,
+        _ => Verdict::Unknown,
+    }
+}
+
+const fn verdict_bits(verdict: Verdict) -> u8 {
+    match verdict {
+        Verdict::Loss => 0,
+        Verdict::Draw => 1,
+        Verdict::Win => 2,
+        Verdict::Unknown => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dimensions::Dims;
+    use crate::engine::{negamax_entrypoint, EngineConfig};
+    use crate::transposition;
+    use rand_mt::Mt64;
+    use std::collections::HashSet;
+
+    type Small = Dims<4, 4>;
+
+    fn connect_three() -> Rules {
+        Rules {
+            connect: 3,
+            ..Rules::default()
+        }
+    }
+
+    #[test]
+    fn test_tablebase_matches_negamax() {
+        let rules = connect_three();
+        let tablebase = Tablebase::<Small>::generate(rules);
+        let table = transposition::Table::default();
+        let config = EngineConfig::default();
+        let mut rng = Mt64::new(1);
+
+        // Every position a game can reach, found by playing every move from the empty board
+        let mut seen = HashSet::new();
+        let mut stack = vec![GameState::<Small>::with_rules(rules)];
+        while let Some(board) = stack.pop() {
+            if !seen.insert(board.clone()) {
+                continue;
+            }
+            let verdict = tablebase.probe(&board).unwrap();
+            if board.gameover_state() != Gameover::None {
+                continue;
+            }
+            let (_, eval) = negamax_entrypoint(&board, &table, &config, &mut rng);
+            let expected = match eval.signum() {
+                1 => Verdict::Win,
+                0 => Verdict::Draw,
+                _ => Verdict::Loss,
+            };
+            assert_eq!(verdict, expected, "{:?}", board);
+            for mv in board.legal_moves() {
+                let mut next = board.clone();
+                next.play(mv);
+                stack.push(next);
+            }
+        }
+    }
+
+    #[test]
+    fn test_red_started_positions_are_probed_with_colours_swapped() {
+        let tablebase = Tablebase::<Small>::generate(connect_three());
+        let mut yellow_started = GameState::<Small>::with_rules(connect_three());
+        let mut red_started = yellow_started.clone();
+        red_started.current_player = Color::Red;
+        for column in [1, 1, 2] {
+            yellow_started.make_move(column);
+            red_started.make_move(column);
+        }
+        assert_eq!(
+            tablebase.probe(&red_started),
+            tablebase.probe(&yellow_started)
+        );
+        assert_ne!(tablebase.probe(&red_started), None);
+
+        // Positions under other rules aren't in the table
+        let other_rules = GameState::<Small>::new();
+        assert_eq!(tablebase.probe(&other_rules), None);
+    }
+
+    #[test]
+    fn test_tablebase_round_trip() {
+        let tablebase = Tablebase::<Dims<3, 3>>::generate(connect_three());
+        let mut bytes = Vec::new();
+        tablebase.write(&mut bytes).unwrap();
+        let read = Tablebase::<Dims<3, 3>>::read(bytes.as_slice()).unwrap();
+        assert_eq!(read.rules(), tablebase.rules());
+        assert_eq!(read.layers, tablebase.layers);
+        assert_eq!(
+            Tablebase::<Small>::read(bytes.as_slice())
+                .err()
+                .map(|error| error.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+    }
+}
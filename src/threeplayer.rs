@@ -0,0 +1,401 @@
+use crate::dimensions::{Bitboard, Dimensions, WideDims};
+use crate::engine::WINNING_EVAL;
+use crate::gamestate::{self, GameState, Move};
+use crate::player::{Analysis, Interrupt, Player, TurnBased};
+use crate::rules::Rules;
+use colored::*;
+use std::fmt;
+use std::ops::ControlFlow;
+
+/// A seat in a three-player game, which takes turns in the order Yellow, Red, Green.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TriColor {
+    Yellow,
+    Red,
+    Green,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriGameover {
+    Win(TriColor),
+    Tie,
+    None,
+}
+
+impl TriColor {
+    pub const ALL: [TriColor; 3] = [TriColor::Yellow, TriColor::Red, TriColor::Green];
+
+    /// The player whose turn comes after this one
+    pub const fn next(&self) -> TriColor {
+        match self {
+            TriColor::Yellow => TriColor::Red,
+            TriColor::Red => TriColor::Green,
+            TriColor::Green => TriColor::Yellow,
+        }
+    }
+
+    /// The player whose turn came before this one
+    pub const fn previous(&self) -> TriColor {
+        match self {
+            TriColor::Yellow => TriColor::Green,
+            TriColor::Red => TriColor::Yellow,
+            TriColor::Green => TriColor::Red,
+        }
+    }
+
+    /// Index of this player's bitboard
+    pub const fn index(&self) -> usize {
+        match self {
+            TriColor::Yellow => 0,
+            TriColor::Red => 1,
+            TriColor::Green => 2,
+        }
+    }
+
+    /// Symbol for this player's pieces on the board
+    fn piece(&self) -> ColoredString {
+        match self {
+            TriColor::Yellow => "Y".yellow(),
+            TriColor::Red => "R".red(),
+            TriColor::Green => "G".green(),
+        }
+    }
+}
+
+impl fmt::Display for TriColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TriColor::Yellow => write!(f, "{}", "Yellow".yellow()),
+            TriColor::Red => write!(f, "{}", "Red".red()),
+            TriColor::Green => write!(f, "{}", "Green".green()),
+        }
+    }
+}
+
+impl fmt::Debug for TriColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.piece())
+    }
+}
+
+/// Connect Four for three players, who each get their own bitboard and take turns in rotation.
+/// Three players crowd a standard board, so it defaults to 9x7.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ThreePlayerGame<D: Dimensions = WideDims<9, 7>> {
+    /// Indexed by [`TriColor::index`]
+    pub pieces: [D::Bits; 3],
+    pub current_player: TriColor,
+    /// Only `connect` and `cylinder` apply to three-player games
+    pub rules: Rules,
+}
+
+impl<D: Dimensions> Default for ThreePlayerGame<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Dimensions> ThreePlayerGame<D> {
+    pub fn new() -> Self {
+        Self::with_rules(Rules::default())
+    }
+
+    pub fn with_rules(rules: Rules) -> Self {
+        ThreePlayerGame {
+            pieces: [D::Bits::ZERO; 3],
+            current_player: TriColor::Yellow,
+            rules,
+        }
+    }
+
+    #[inline(always)]
+    pub fn filled(&self) -> D::Bits {
+        self.pieces[0] | self.pieces[1] | self.pieces[2]
+    }
+
+    #[inline(always)]
+    pub fn get_height(&self, column: u8) -> u8 {
+        let col_bits = (self.filled() >> (column as u32 * D::STRIDE as u32))
+            & D::Bits::from_u128(D::COLUMN_MASK);
+        col_bits.trailing_ones() as u8
+    }
+
+    /// Whether a piece can still be dropped into `column`
+    #[inline(always)]
+    pub fn can_play(&self, column: u8) -> bool {
+        self.get_height(column) < D::HEIGHT
+    }
+
+    /// Every column that isn't full, with the center columns first
+    pub fn legal_moves(&self) -> Vec<u8> {
+        D::column_order()
+            .into_iter()
+            .filter(|&column| self.can_play(column))
+            .collect()
+    }
+
+    /// Make a move in the specified column, then pass the turn to the next player.
+    ///
+    /// Returns `true` if the move was successful, `false` if the column is full.
+    pub fn make_move(&mut self, column: u8) -> bool {
+        if column >= D::WIDTH {
+            return false; // Not on the board
+        }
+        let height = self.get_height(column);
+        if height >= D::HEIGHT {
+            return false; // Column is full
+        }
+        self.pieces[self.current_player.index()] |= GameState::<D>::cell_mask(column, height);
+        self.current_player = self.current_player.next();
+        true
+    }
+
+    /// Undo a move made at a specific column
+    pub fn undo_move(&mut self, column: u8) {
+        let mask = !GameState::<D>::cell_mask(column, self.get_height(column) - 1);
+        self.current_player = self.current_player.previous();
+        self.pieces[self.current_player.index()] &= mask;
+    }
+
+    pub fn gameover_state(&self) -> TriGameover {
+        // Only the player who just moved can have completed a line
+        let previous_player = self.current_player.previous();
+        if GameState::<D>::has_won(&self.rules, self.pieces[previous_player.index()]) {
+            return TriGameover::Win(previous_player);
+        }
+        if self.filled().count_ones() == D::CELLS {
+            TriGameover::Tie
+        } else {
+            TriGameover::None
+        }
+    }
+}
+
+/// Three-player games only have drops.
+impl<D: Dimensions> TurnBased for ThreePlayerGame<D> {
+    type Seat = TriColor;
+    type Move = Move;
+
+    fn to_move(&self) -> TriColor {
+        self.current_player
+    }
+
+    fn moves(&self) -> Vec<Move> {
+        self.legal_moves().into_iter().map(Move::Drop).collect()
+    }
+
+    fn play_move(&mut self, mv: Move) {
+        assert!(self.make_move(mv.column()), "Legal moves must be playable");
+    }
+
+    fn winner(&self) -> Option<TriColor> {
+        match self.gameover_state() {
+            TriGameover::Win(color) => Some(color),
+            TriGameover::Tie | TriGameover::None => None,
+        }
+    }
+}
+
+/// The depth-limited [`paranoid_search`], since the usual engines only know about two players.
+pub struct ParanoidPlayer {
+    max_depth: u16,
+    last_analysis: Option<Analysis>,
+}
+
+impl ParanoidPlayer {
+    pub fn new(max_depth: u16) -> Self {
+        ParanoidPlayer {
+            max_depth,
+            last_analysis: None,
+        }
+    }
+}
+
+impl<D: Dimensions> Player<ThreePlayerGame<D>> for ParanoidPlayer {
+    fn name(&self) -> &str {
+        "Computer"
+    }
+
+    fn last_eval(&self) -> Option<i32> {
+        match &self.last_analysis {
+            Some(Analysis::Evals { evals, .. }) => evals.first().map(|&(_, eval)| eval),
+            _ => None,
+        }
+    }
+
+    fn last_analysis(&self) -> Option<&Analysis> {
+        self.last_analysis.as_ref()
+    }
+
+    fn choose_move(&mut self, game: &ThreePlayerGame<D>) -> ControlFlow<Interrupt, Move> {
+        let evals: Vec<(Move, i32)> = evaluate_columns(game, self.max_depth)
+            .into_iter()
+            .map(|(column, eval)| (Move::Drop(column), eval))
+            .collect();
+        let (mv, _) = evals[0];
+        self.last_analysis = Some(Analysis::Evals {
+            evals,
+            cleared_entries: None,
+        });
+        ControlFlow::Continue(mv)
+    }
+}
+
+/// Search the `game` up to `max_depth` plies with paranoid search, and return the best column with its eval.
+pub fn paranoid_search<D: Dimensions>(game: &ThreePlayerGame<D>, max_depth: u16) -> (u8, i32) {
+    evaluate_columns(game, max_depth)[0]
+}
+
+/// Eval of every open column after a paranoid search of `max_depth` plies, from the point of view of the player to move, best first.
+/// Columns with equal evals keep the order of [`ThreePlayerGame::legal_moves`], which puts central columns first.
+///
+/// Paranoid search assumes the two opponents are working together against the player to move.
+/// That turns the game back into a two-sided one, so alpha-beta pruning still works.
+pub fn evaluate_columns<D: Dimensions>(
+    game: &ThreePlayerGame<D>,
+    max_depth: u16,
+) -> Vec<(u8, i32)> {
+    let mut game = game.clone();
+    let root = game.current_player;
+    let mut results = Vec::new();
+    for column in game.legal_moves() {
+        game.make_move(column);
+        let eval = paranoid(&mut game, root, -10_000, 10_000, 1, max_depth);
+        game.undo_move(column);
+        results.push((column, eval));
+    }
+    assert!(!results.is_empty(), "Must have at least one valid move");
+    results.sort_by_key(|result| -result.1);
+    results
+}
+
+/// Eval of `game` from the point of view of `root`, who maximizes while both opponents minimize.
+/// Positions cut off by `max_depth` are scored as 0, since their outcome is unknown.
+fn paranoid<D: Dimensions>(
+    game: &mut ThreePlayerGame<D>,
+    root: TriColor,
+    mut alpha: i32,
+    mut beta: i32,
+    ply: u16,
+    max_depth: u16,
+) -> i32 {
+    match game.gameover_state() {
+        // We subtract the ply to encourage winning quickly, and add it to drag out losing games
+        TriGameover::Win(color) if color == root => return WINNING_EVAL - ply as i32,
+        TriGameover::Win(_) => return ply as i32 - WINNING_EVAL,
+        TriGameover::Tie => return 0,
+        TriGameover::None => {}
+    }
+    if ply >= max_depth {
+        return 0;
+    }
+
+    let maximizing = game.current_player == root;
+    let mut best_eval = if maximizing { -20_000 } else { 20_000 };
+    for column in game.legal_moves() {
+        game.make_move(column);
+        let eval = paranoid(game, root, alpha, beta, ply + 1, max_depth);
+        game.undo_move(column);
+
+        if maximizing {
+            best_eval = best_eval.max(eval);
+            alpha = alpha.max(best_eval);
+        } else {
+            best_eval = best_eval.min(eval);
+            beta = beta.min(best_eval);
+        }
+        if alpha >= beta {
+            break;
+        }
+    }
+    best_eval
+}
+
+impl<D: Dimensions> fmt::Debug for ThreePlayerGame<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:} to play", self.current_player)?;
+        gamestate::write_board::<D>(f, self.rules.cylinder, D::Bits::ZERO, |mask| {
+            TriColor::ALL
+                .iter()
+                .find(|color| self.pieces[color.index()] & mask != D::Bits::ZERO)
+                .map_or(" ".white(), TriColor::piece)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turns_rotate_and_undo() {
+        let mut game: ThreePlayerGame = ThreePlayerGame::new();
+        for column in [4, 4, 4] {
+            game.make_move(column);
+        }
+        assert_eq!(game.current_player, TriColor::Yellow);
+        for (row, color) in TriColor::ALL.iter().enumerate() {
+            let mask = GameState::<WideDims<9, 7>>::cell_mask(4, row as u8);
+            assert_ne!(game.pieces[color.index()] & mask, 0);
+        }
+        game.undo_move(4);
+        assert_eq!(game.current_player, TriColor::Green);
+        assert_eq!(game.get_height(4), 2);
+    }
+
+    #[test]
+    fn test_moves_off_the_board_are_rejected() {
+        let mut game = ThreePlayerGame::<crate::dimensions::Standard>::new();
+        assert!(!game.make_move(7));
+        assert_eq!(game.filled(), 0);
+        assert_eq!(game.current_player, TriColor::Yellow);
+    }
+
+    #[test]
+    fn test_win_for_third_player() {
+        let mut game: ThreePlayerGame = ThreePlayerGame::new();
+        // Green builds on the bottom row while the others stack elsewhere
+        for column in [0, 0, 1, 0, 0, 2, 8, 8, 3, 8, 7] {
+            game.make_move(column);
+        }
+        assert_eq!(game.gameover_state(), TriGameover::None);
+        game.make_move(4);
+        assert_eq!(game.gameover_state(), TriGameover::Win(TriColor::Green));
+    }
+
+    #[test]
+    fn test_paranoid_player_follows_the_rules() {
+        use crate::dimensions::Standard;
+
+        // Green blocks column 3, so Red can only win by wrapping around from column 7 to columns 1 and 2
+        let mut game = ThreePlayerGame::<Standard>::with_rules(Rules {
+            connect: 3,
+            cylinder: true,
+            ..Rules::default()
+        });
+        for column in [3, 0, 2, 3, 1, 5, 5] {
+            game.make_move(column);
+        }
+        assert_eq!(game.current_player, TriColor::Red);
+        let mut player = ParanoidPlayer::new(1);
+        assert_eq!(
+            player.choose_move(&game),
+            ControlFlow::Continue(Move::Drop(6))
+        );
+        game.play_move(Move::Drop(6));
+        assert_eq!(game.winner(), Some(TriColor::Red));
+    }
+
+    #[test]
+    fn test_paranoid_search_takes_immediate_win() {
+        let mut game: ThreePlayerGame = ThreePlayerGame::new();
+        // Yellow gets three on the bottom row, Red and Green stack in the far columns
+        for column in [2, 0, 8, 3, 0, 8, 4, 0, 8] {
+            game.make_move(column);
+        }
+        assert_eq!(game.current_player, TriColor::Yellow);
+        let (column, eval) = paranoid_search(&game, 3);
+        assert!(column == 1 || column == 5, "{}", column);
+        assert!(eval > 0);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::color::Color;
+use crate::dimensions::{Bitboard, Dimensions, Standard};
 use crate::gamestate::GameState;
 use rand_mt::Mt64;
 use std::collections::HashMap;
@@ -9,19 +10,21 @@ const MAX_TABLE_SIZE: usize = 80_000_000;
 const CLEAR_THRESHOLD: f32 = 0.9;
 const CLEAR_SIZE: usize = (MAX_TABLE_SIZE as f32 * CLEAR_THRESHOLD) as usize;
 
-pub type Table = RwLock<HashMap<u64, Entry>>;
+pub type Table<D = Standard> = RwLock<HashMap<u64, Entry<D>>>;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Entry {
-    gamestate: GameState,
+pub struct Entry<D: Dimensions = Standard> {
+    gamestate: GameState<D>,
     eval: i32,
 }
 
-pub fn new_table() -> Table {
+/// Table with room for a full solve reserved up front, which takes several gigabytes.
+/// Use `Table::default()` for a small table that grows as needed.
+pub fn new_table<D: Dimensions>() -> Table<D> {
     RwLock::new(HashMap::with_capacity(MAX_TABLE_SIZE))
 }
 
-pub fn store_entry(table: &Table, gamestate: &GameState, eval: i32) {
+pub(crate) fn store_entry<D: Dimensions>(table: &Table<D>, gamestate: &GameState<D>, eval: i32) {
     let hash = compute_hash(gamestate);
     let mut table = table.write().expect("rw lock on tt to not be poisoned");
     let old_entry = table.get(&hash);
@@ -41,56 +44,105 @@ pub fn store_entry(table: &Table, gamestate: &GameState, eval: i32) {
     table.insert(hash, new_entry);
 }
 
-pub fn check_for_table_clear(table: &Table) {
+/// Clear the table once it holds too many entries.
+/// Returns how many entries were cleared, or `None` if the table was left alone.
+pub fn check_for_table_clear<D: Dimensions>(table: &Table<D>) -> Option<usize> {
     let mut table = table.write().expect("rw lock on tt to not be poisoned");
-    if table.len() >= CLEAR_SIZE {
-        println!(
-            "Transposition table reached {} entries, clearing...",
-            table.len()
-        );
+    let entries = table.len();
+    if entries >= CLEAR_SIZE {
         table.clear();
+        Some(entries)
+    } else {
+        None
     }
 }
 
-pub fn probe_eval(table: &Table, gamestate: &GameState) -> Option<i32> {
+pub(crate) fn probe_eval<D: Dimensions>(table: &Table<D>, gamestate: &GameState<D>) -> Option<i32> {
     let hash = compute_hash(gamestate);
     let table = table.read().expect("rw lock on tt to not be poisoned");
     let entry = table.get(&hash)?;
-    Some(entry.eval)
+    // Different positions can share a hash, so only trust the entry if it is for this position
+    (entry.gamestate == *gamestate).then_some(entry.eval)
 }
 
-/// tuple.0 is a vector of (red, yellow) hashes for each square,
-/// tuple.1 is the hashes for current_player, (red, yellow).
-static ZOBRIST_TABLE: LazyLock<(Vec<(u64, u64)>, (u64, u64))> = LazyLock::new(|| {
-    const BOARD_SIZE: usize = 49;
-    let mut table = vec![(0u64, 0u64); BOARD_SIZE];
+/// (red, yellow) pair of Zobrist keys.
+type KeyPair = (u64, u64);
+
+struct ZobristKeys {
+    /// (red, yellow) keys for each square
+    squares: Vec<KeyPair>,
+    /// Keys for an obstacle on each square
+    obstacles: Vec<u64>,
+    /// Keys for the current player, (red, yellow)
+    current_player: KeyPair,
+    /// Keys for each connect length, so positions under different rules never share an entry
+    connect: Vec<u64>,
+    popout: u64,
+    misere: u64,
+    cylinder: u64,
+}
+
+static ZOBRIST_TABLE: LazyLock<ZobristKeys> = LazyLock::new(|| {
+    // Enough squares for the largest bitboard, including sentinel rows
+    const BOARD_SIZE: usize = u128::BITS as usize;
     let mut rng = Mt64::new_unseeded();
-    for square in 0..BOARD_SIZE {
-        table[square] = (rng.next_u64(), rng.next_u64());
+    let squares = (0..BOARD_SIZE)
+        .map(|_| (rng.next_u64(), rng.next_u64()))
+        .collect();
+    let current_player = (rng.next_u64(), rng.next_u64());
+    let obstacles = (0..BOARD_SIZE).map(|_| rng.next_u64()).collect();
+    let connect = (0..=u8::MAX).map(|_| rng.next_u64()).collect();
+    ZobristKeys {
+        squares,
+        obstacles,
+        current_player,
+        connect,
+        popout: rng.next_u64(),
+        misere: rng.next_u64(),
+        cylinder: rng.next_u64(),
     }
-    (table, (rng.next_u64(), rng.next_u64()))
 });
 
-fn compute_hash(game_state: &GameState) -> u64 {
+fn compute_hash<D: Dimensions>(game_state: &GameState<D>) -> u64 {
     let mut hash = 0u64;
     hash = hash_bitboard(hash, game_state.yellow, Color::Yellow);
     hash = hash_bitboard(hash, game_state.red, Color::Red);
     hash ^= match game_state.current_player {
-        Color::Red => ZOBRIST_TABLE.1 .0,
-        Color::Yellow => ZOBRIST_TABLE.1 .1,
+        Color::Red => ZOBRIST_TABLE.current_player.0,
+        Color::Yellow => ZOBRIST_TABLE.current_player.1,
     };
+
+    let mut obstacles = game_state.obstacles;
+    while obstacles != D::Bits::ZERO {
+        let index = obstacles.trailing_zeros();
+        hash ^= ZOBRIST_TABLE.obstacles[index as usize];
+        obstacles ^= D::Bits::ONE << index;
+    }
+
+    let rules = game_state.rules;
+    hash ^= ZOBRIST_TABLE.connect[rules.connect as usize];
+    for (enabled, key) in [
+        (rules.popout, ZOBRIST_TABLE.popout),
+        (rules.misere, ZOBRIST_TABLE.misere),
+        (rules.cylinder, ZOBRIST_TABLE.cylinder),
+    ] {
+        if enabled {
+            hash ^= key;
+        }
+    }
     hash
 }
 
 /// Hash a bitboard with a color, starting with the given hash and returning a new hash.
-fn hash_bitboard(mut hash: u64, mut bitboard: u64, color: Color) -> u64 {
-    while bitboard > 0 {
-        let square = ZOBRIST_TABLE.0[bitboard.trailing_zeros() as usize];
+fn hash_bitboard<B: Bitboard>(mut hash: u64, mut bitboard: B, color: Color) -> u64 {
+    while bitboard != B::ZERO {
+        let index = bitboard.trailing_zeros();
+        let square = ZOBRIST_TABLE.squares[index as usize];
         hash ^= match color {
             Color::Red => square.0,
             Color::Yellow => square.1,
         };
-        bitboard &= bitboard - 1;
+        bitboard ^= B::ONE << index;
     }
     hash
 }
@@ -0,0 +1,60 @@
+//! Uses the library the way an outside tool would, through the public API only.
+
+use connect_four_bot::{
+    check_for_table_clear, negamax_entrypoint, solve, Color, Dims, EngineConfig, FenError, Game,
+    GameState, Gameover, Move, Mt64, Standard, Table, Verdict,
+};
+
+#[test]
+fn test_positions_round_trip_through_fen() {
+    let board: GameState = GameState::from_moves("4453342").unwrap();
+    assert_eq!(board.current_player, Color::Red);
+    let fen = board.to_fen();
+    assert_eq!(GameState::from_fen(&fen, None).unwrap(), board);
+    assert_eq!(
+        GameState::<Standard>::from_fen("......./......./yyy....", None),
+        Err(FenError::WrongRowCount {
+            expected: 6,
+            found: 3
+        })
+    );
+}
+
+#[test]
+fn test_games_end_when_a_line_is_made() {
+    let mut game: Game = Game::default();
+    for column in [0, 1, 0, 1, 0, 1] {
+        game.play(Move::Drop(column)).unwrap();
+        assert_eq!(game.board().gameover_state(), Gameover::None);
+    }
+    game.play(Move::Drop(0)).unwrap();
+    assert_eq!(game.board().gameover_state(), Gameover::Win(Color::Yellow));
+    assert_eq!(game.undo(), Ok(Move::Drop(0)));
+    assert_eq!(game.board().gameover_state(), Gameover::None);
+}
+
+#[test]
+fn test_engine_takes_immediate_win() {
+    let board: GameState =
+        GameState::from_fen("......./......./......./......./rrr..../yyy....", None).unwrap();
+    let table = Table::default();
+    let config = EngineConfig {
+        max_depth: Some(2),
+        blunder_chance: 0.0,
+        randomize_ties: false,
+    };
+    let (mv, eval) = negamax_entrypoint(&board, &table, &config, &mut Mt64::new(1));
+    assert_eq!(mv, Move::Drop(3));
+    assert!(eval > 0);
+    assert_eq!(check_for_table_clear(&table), None);
+}
+
+#[test]
+fn test_engine_and_proof_search_agree_on_small_board() {
+    // Connect Four on a 4x4 board is a draw
+    let board = GameState::<Dims<4, 4>>::new();
+    let table = Table::default();
+    let (_, eval) = negamax_entrypoint(&board, &table, &EngineConfig::default(), &mut Mt64::new(1));
+    assert_eq!(eval, 0);
+    assert_eq!(solve(&board, 1_000_000).verdict, Verdict::Draw);
+}